@@ -3,7 +3,9 @@ extern crate criterion;
 extern crate sketchy;
 
 use criterion::Criterion;
-use sketchy::{BloomFilter, CountMinSketch, HyperLogLog, ReservoirSample, TopK};
+use sketchy::{
+    BlockedBloomFilter, BloomFilter, CountMinSketch, HyperLogLog, ReservoirSample, TopK,
+};
 
 fn bloomf_insert(c: &mut Criterion) {
     let mut bf = BloomFilter::new(100_000, 0.01);
@@ -12,6 +14,35 @@ fn bloomf_insert(c: &mut Criterion) {
     });
 }
 
+/// The number of elements in the filters `contains` is benchmarked with, which at 1% false
+/// positives take up about 80MB, more than a typical last-level cache.
+const LARGE: u64 = 64_000_000;
+
+/// Returns a million distinct keys to query the large filters with, alternating between elements
+/// which were inserted and ones which weren't, so that queries miss the cache as they would in a
+/// real workload instead of hitting the same few cache lines.
+fn queries() -> Vec<u64> {
+    (0..1_000_000u64)
+        .map(|i| if i % 2 == 0 { i * 61 } else { LARGE + i })
+        .collect()
+}
+
+fn bloomf_contains(c: &mut Criterion) {
+    let mut bf = BloomFilter::new(LARGE as usize, 0.01);
+    for i in 0..LARGE {
+        bf.insert(i);
+    }
+
+    let queries = queries();
+    let mut i = 0;
+    c.bench_function("BloomFilter::contains", move |b| {
+        b.iter(|| {
+            i = (i + 1) % queries.len();
+            bf.contains(&queries[i])
+        })
+    });
+}
+
 fn bloomf_merge(c: &mut Criterion) {
     let mut bf1 = BloomFilter::new(100_000, 0.01);
     bf1.insert("this is the end");
@@ -24,6 +55,41 @@ fn bloomf_merge(c: &mut Criterion) {
     });
 }
 
+fn blocked_insert(c: &mut Criterion) {
+    let mut bf = BlockedBloomFilter::new(100_000, 0.01);
+    c.bench_function("BlockedBloomFilter::insert", move |b| {
+        b.iter(|| bf.insert("this is the end"))
+    });
+}
+
+fn blocked_contains(c: &mut Criterion) {
+    let mut bf = BlockedBloomFilter::new(LARGE as usize, 0.01);
+    for i in 0..LARGE {
+        bf.insert(i);
+    }
+
+    let queries = queries();
+    let mut i = 0;
+    c.bench_function("BlockedBloomFilter::contains", move |b| {
+        b.iter(|| {
+            i = (i + 1) % queries.len();
+            bf.contains(&queries[i])
+        })
+    });
+}
+
+fn blocked_merge(c: &mut Criterion) {
+    let mut bf1 = BlockedBloomFilter::new(100_000, 0.01);
+    bf1.insert("this is the end");
+
+    let mut bf2 = BlockedBloomFilter::new(100_000, 0.01);
+    bf2.insert("this is the beginning");
+
+    c.bench_function("BlockedBloomFilter::merge", move |b| {
+        b.iter(|| bf1.merge(&bf2))
+    });
+}
+
 fn cms_insert(c: &mut Criterion) {
     let mut cms = CountMinSketch::with_confidence(0.0001, 0.99);
 
@@ -91,7 +157,11 @@ fn topk_insert(c: &mut Criterion) {
 criterion_group!(
     benches,
     bloomf_insert,
+    bloomf_contains,
     bloomf_merge,
+    blocked_insert,
+    blocked_contains,
+    blocked_merge,
     cms_insert,
     cms_insert_n,
     cms_estimate,
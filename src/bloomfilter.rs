@@ -1,8 +1,13 @@
 use bit_vec::BitVec;
-use std::hash::Hash;
+use std::hash::{BuildHasher, Hash};
 use std::marker::PhantomData;
+use std::mem;
 
-use hash::indexes;
+use bloomparams::BloomParams;
+use codec;
+use error::Error;
+use hash::{fingerprint, indexes, SipBuildHasher};
+use sketch::{Estimator, Mergeable, Sketch};
 
 /// A Bloom filter is a space-efficient probabilistic data structure that is used to test whether an
 /// element is a member of a set. False positive matches are possible, but false negatives are not.
@@ -19,34 +24,216 @@ use hash::indexes;
 ///
 /// assert!(filter.contains(&"one"));
 /// ```
-pub struct BloomFilter<E> {
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        bound(
+            serialize = "S: ::serde::Serialize",
+            deserialize = "S: ::serde::Deserialize<'de>"
+        ),
+        try_from = "BloomFilterData<S>"
+    )
+)]
+pub struct BloomFilter<E, S = SipBuildHasher> {
     k: usize,
+    #[cfg_attr(feature = "serde", serde(with = "bits"))]
     bits: BitVec,
+    hasher: S,
+    #[cfg_attr(feature = "serde", serde(skip))]
     marker: PhantomData<E>,
 }
 
 impl<E: Hash> BloomFilter<E> {
     /// Creates a new `BloomFilter` instance, tuned for a population of `n` elements with the given
     /// upper bound of the probability of false positives.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the probability isn't in the range `(0, 1)` or the filter would be too large.
     pub fn new(n: usize, max_false_pos_prob: f64) -> BloomFilter<E> {
-        let (buckets, k) = best_buckets_and_k(max_false_pos_prob);
-        BloomFilter::<E> {
+        match BloomFilter::try_new(n, max_false_pos_prob) {
+            Ok(bf) => bf,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Creates a new `BloomFilter` instance, tuned for a population of `n` elements with the given
+    /// upper bound of the probability of false positives, or returns an error if the probability
+    /// isn't in the range `(0, 1)` or the filter would be too large.
+    pub fn try_new(n: usize, max_false_pos_prob: f64) -> Result<BloomFilter<E>, Error> {
+        BloomFilter::try_with_hasher(n, max_false_pos_prob, SipBuildHasher::default())
+    }
+
+    /// Creates a new `BloomFilter` instance with a bit vector of the given length and the given
+    /// number of hash functions, or returns an error if either is zero or there are more hash
+    /// functions than bits. `BloomParams` can solve for these, e.g. to size a filter for a lower
+    /// probability of false positives than `new` supports, or to match the layout of another
+    /// filter.
+    pub fn with_params(bits: usize, k: usize) -> Result<BloomFilter<E>, Error> {
+        BloomFilter::with_params_and_hasher(bits, k, SipBuildHasher::default())
+    }
+
+    /// Returns the filter encoded in a compact, versioned binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = codec::header(codec::BLOOM_FILTER, codec::HASH_SIPHASH24);
+        self.hasher.encode(&mut buf);
+        self.encode(&mut buf);
+        codec::finish(buf)
+    }
+
+    /// Decodes a filter from the format produced by `to_bytes`, returning an error if the data is
+    /// corrupt or isn't an encoded `BloomFilter`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<BloomFilter<E>, Error> {
+        let mut input = codec::open(bytes, codec::BLOOM_FILTER, codec::HASH_SIPHASH24)?;
+        let hasher = SipBuildHasher::decode(&mut input)?;
+        let bf = BloomFilter::decode(&mut input, hasher)?;
+        codec::close(input)?;
+        Ok(bf)
+    }
+
+    /// Appends the parameters and bits of the filter, but not its hasher, to the buffer.
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        codec::put_varint(buf, self.k as u64);
+        codec::put_varint(buf, self.bits.len() as u64);
+        buf.extend_from_slice(&self.bits.to_bytes());
+    }
+
+    /// Decodes the parameters and bits of a filter with the given hasher from the front of the
+    /// input.
+    pub(crate) fn decode(
+        input: &mut &[u8],
+        hasher: SipBuildHasher,
+    ) -> Result<BloomFilter<E>, Error> {
+        let k = codec::get_varint(input)? as usize;
+        let len = codec::get_varint(input)?;
+        check_params(k, len)?;
+
+        let mut bits = BitVec::from_bytes(codec::get_bytes(input, len.div_ceil(8) as usize)?);
+        bits.truncate(len as usize);
+
+        Ok(BloomFilter::<E> {
             k,
-            bits: BitVec::from_elem(n * buckets + 20, false),
+            bits,
+            hasher,
             marker: PhantomData,
+        })
+    }
+}
+
+impl<E: Hash, S: BuildHasher> BloomFilter<E, S> {
+    /// Creates a new `BloomFilter` instance, tuned for a population of `n` elements with the given
+    /// upper bound of the probability of false positives, which hashes elements with the given
+    /// hasher.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the probability isn't in the range `(0, 1)` or the filter would be too large.
+    pub fn with_hasher(n: usize, max_false_pos_prob: f64, hasher: S) -> BloomFilter<E, S> {
+        match BloomFilter::try_with_hasher(n, max_false_pos_prob, hasher) {
+            Ok(bf) => bf,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Creates a new `BloomFilter` instance, tuned for a population of `n` elements with the given
+    /// upper bound of the probability of false positives, which hashes elements with the given
+    /// hasher. Returns an error if the probability isn't in the range `(0, 1)` or the filter would
+    /// be too large.
+    pub fn try_with_hasher(
+        n: usize,
+        max_false_pos_prob: f64,
+        hasher: S,
+    ) -> Result<BloomFilter<E, S>, Error> {
+        let (len, k) = size_and_k(n, max_false_pos_prob)?;
+        Ok(BloomFilter::<E, S> {
+            k,
+            bits: BitVec::from_elem(len, false),
+            hasher,
+            marker: PhantomData,
+        })
+    }
+
+    /// Creates a new `BloomFilter` instance with a bit vector of the given length and the given
+    /// number of hash functions, which hashes elements with the given hasher. Returns an error if
+    /// either is zero or there are more hash functions than bits, which `from_bytes` would reject.
+    pub fn with_params_and_hasher(
+        bits: usize,
+        k: usize,
+        hasher: S,
+    ) -> Result<BloomFilter<E, S>, Error> {
+        if bits == 0 {
+            return Err(Error::InvalidParameter("number of bits must be positive"));
+        }
+
+        if k == 0 {
+            return Err(Error::InvalidParameter(
+                "number of hash functions must be positive",
+            ));
+        }
+
+        if k > bits {
+            return Err(Error::InvalidParameter("too many hash functions"));
         }
+
+        Ok(BloomFilter::<E, S> {
+            k,
+            bits: BitVec::from_elem(bits, false),
+            hasher,
+            marker: PhantomData,
+        })
+    }
+
+    /// Returns the hasher used to hash elements.
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    /// Returns the number of hash functions, i.e. the number of bits set per element.
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// Returns the length of the filter's bit vector.
+    pub fn num_bits(&self) -> usize {
+        self.bits.len()
+    }
+
+    /// Returns the number of bits which are set.
+    pub fn count_ones(&self) -> usize {
+        self.bits.blocks().map(|b| b.count_ones() as usize).sum()
+    }
+
+    /// Returns the fraction of bits which are set.
+    pub fn fill_ratio(&self) -> f64 {
+        self.count_ones() as f64 / self.bits.len() as f64
+    }
+
+    /// Returns an estimate of the number of distinct elements which have been inserted, using the
+    /// formula from [Mathematical Correction for Fingerprint Similarity
+    /// Measures](https://doi.org/10.1021/ci600526a) by Swamidass and Baldi. Returns infinity if
+    /// every bit is set.
+    pub fn cardinality(&self) -> f64 {
+        estimate_cardinality(self.count_ones(), self.bits.len(), self.k)
+    }
+
+    /// Returns the probability that a query for an element which wasn't inserted returns `true`,
+    /// given the bits which are currently set. This grows past the filter's designed probability
+    /// once more elements have been inserted than it was sized for.
+    pub fn false_pos_prob(&self) -> f64 {
+        self.fill_ratio().powi(self.k as i32)
     }
 
     /// Adds a value to the set.
     pub fn insert(&mut self, e: E) {
-        for i in indexes(&e, self.bits.len()).take(self.k) {
+        for i in indexes(&e, self.bits.len(), &self.hasher).take(self.k) {
             self.bits.set(i, true);
         }
     }
 
     /// Returns `true` if the set probably contains the given element.
-    pub fn contains(&mut self, e: &E) -> bool {
-        for i in indexes(e, self.bits.len()).take(self.k) {
+    pub fn contains(&self, e: &E) -> bool {
+        for i in indexes(e, self.bits.len(), &self.hasher).take(self.k) {
             if !self.bits.get(i).unwrap() {
                 return false;
             }
@@ -55,30 +242,236 @@ impl<E: Hash> BloomFilter<E> {
     }
 
     /// Merges the contents of the given `BloomFilter` into `self`. Both filters must have the same
-    /// parameters. Returns true if self changed.
+    /// parameters and hasher. Returns true if self changed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bloom filters have different parameters or hashers.
+    pub fn merge(&mut self, other: &BloomFilter<E, S>) -> bool {
+        match self.try_merge(other) {
+            Ok(changed) => changed,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Merges the contents of the given `BloomFilter` into `self`, returning true if self changed,
+    /// or an error if the filters have different parameters or hashers.
+    pub fn try_merge(&mut self, other: &BloomFilter<E, S>) -> Result<bool, Error> {
+        self.check_compatible(other)?;
+        Ok(self.bits.union(&other.bits))
+    }
+
+    /// Intersects `self` with the given `BloomFilter`, keeping only the bits set in both. Both
+    /// filters must have the same parameters and hasher. Returns true if self changed.
+    ///
+    /// The result contains every element which was inserted into both filters, but it has a higher
+    /// probability of false positives than a filter of only those elements would.
     ///
     /// # Panics
     ///
-    /// Panics if the bloom filters have different parameters.
-    pub fn merge(&mut self, other: &BloomFilter<E>) -> bool {
-        assert_eq!(self.k, other.k);
-        self.bits.union(&other.bits)
+    /// Panics if the bloom filters have different parameters or hashers.
+    pub fn intersect(&mut self, other: &BloomFilter<E, S>) -> bool {
+        match self.try_intersect(other) {
+            Ok(changed) => changed,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Intersects `self` with the given `BloomFilter`, returning true if self changed, or an error
+    /// if the filters have different parameters or hashers.
+    pub fn try_intersect(&mut self, other: &BloomFilter<E, S>) -> Result<bool, Error> {
+        self.check_compatible(other)?;
+        Ok(self.bits.intersect(&other.bits))
+    }
+
+    /// Returns an estimate of the number of distinct elements in the union of the two filters'
+    /// sets, from the number of bits set in either, or an error if the filters have different
+    /// parameters or hashers.
+    pub fn union_cardinality(&self, other: &BloomFilter<E, S>) -> Result<f64, Error> {
+        self.check_compatible(other)?;
+        let ones = self
+            .bits
+            .blocks()
+            .zip(other.bits.blocks())
+            .map(|(a, b)| (a | b).count_ones() as usize)
+            .sum();
+        Ok(estimate_cardinality(ones, self.bits.len(), self.k))
+    }
+
+    /// Returns an estimate of the number of distinct elements in the intersection of the two
+    /// filters' sets, or an error if the filters have different parameters or hashers.
+    ///
+    /// This uses the inclusion–exclusion principle, `|A ∩ B| = |A| + |B| - |A ∪ B|`, which is more
+    /// accurate than counting the bits set in both filters, since those include bits set by
+    /// different elements of each set.
+    pub fn intersection_cardinality(&self, other: &BloomFilter<E, S>) -> Result<f64, Error> {
+        let u = self.union_cardinality(other)?;
+        let (a, b) = (self.cardinality(), other.cardinality());
+        Ok((a + b - u).max(0.0).min(a.min(b)))
+    }
+
+    /// Returns an estimate of the Jaccard similarity of the two filters' sets, `|A ∩ B| / |A ∪ B|`,
+    /// or an error if the filters have different parameters or hashers. Returns zero if both sets
+    /// are empty.
+    pub fn jaccard(&self, other: &BloomFilter<E, S>) -> Result<f64, Error> {
+        let u = self.union_cardinality(other)?;
+        if u == 0.0 {
+            return Ok(0.0);
+        }
+        let (a, b) = (self.cardinality(), other.cardinality());
+        Ok((a + b - u).max(0.0).min(a.min(b)) / u)
     }
 
     /// Clears the bit vector, removing all elements from the set
     pub fn clear(&mut self) {
         self.bits.clear();
     }
+
+    /// Returns the approximate amount of memory used by the filter, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>() + self.bits.capacity() / 8
+    }
+}
+
+impl<E: Hash, S: BuildHasher> Sketch<E> for BloomFilter<E, S> {
+    fn insert(&mut self, e: E) {
+        BloomFilter::insert(self, e)
+    }
+
+    fn clear(&mut self) {
+        BloomFilter::clear(self)
+    }
+
+    fn memory_usage(&self) -> usize {
+        BloomFilter::memory_usage(self)
+    }
+}
+
+impl<E: Hash, S: BuildHasher> Mergeable for BloomFilter<E, S> {
+    fn check_compatible(&self, other: &Self) -> Result<(), Error> {
+        if self.k != other.k || self.bits.len() != other.bits.len() {
+            return Err(Error::Incompatible("different parameters"));
+        }
+
+        if fingerprint(&self.hasher) != fingerprint(&other.hasher) {
+            return Err(Error::HasherMismatch);
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: &Self) -> Result<(), Error> {
+        self.try_merge(other).map(|_| ())
+    }
+}
+
+impl<E: Hash, S: BuildHasher> Estimator<E> for BloomFilter<E, S> {
+    type Estimate = bool;
+
+    fn estimate(&self, e: &E) -> bool {
+        self.contains(e)
+    }
+}
+
+/// Returns the Swamidass–Baldi estimate of the number of distinct elements in a filter of `m` bits
+/// and `k` hash functions with `ones` bits set: `-(m / k) ln(1 - ones / m)`.
+fn estimate_cardinality(ones: usize, m: usize, k: usize) -> f64 {
+    let m = m as f64;
+    -(m / k as f64) * (1.0 - ones as f64 / m).ln()
+}
+
+/// Returns an error if a decoded filter with `k` hash functions and `len` bits would be empty or
+/// do more work per element than it has bits.
+fn check_params(k: usize, len: u64) -> Result<(), Error> {
+    if k == 0 || len == 0 {
+        return Err(Error::InvalidData("empty filter"));
+    }
+
+    if k as u64 > len {
+        return Err(Error::InvalidData("too many hash functions"));
+    }
+    Ok(())
 }
 
-fn best_buckets_and_k(max_false_pos_prob: f64) -> (usize, usize) {
+/// The fields of a deserialized `BloomFilter`, which are checked before use.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct BloomFilterData<S> {
+    k: usize,
+    #[serde(with = "bits")]
+    bits: BitVec,
+    hasher: S,
+}
+
+#[cfg(feature = "serde")]
+impl<E, S> ::std::convert::TryFrom<BloomFilterData<S>> for BloomFilter<E, S> {
+    type Error = Error;
+
+    fn try_from(data: BloomFilterData<S>) -> Result<BloomFilter<E, S>, Error> {
+        check_params(data.k, data.bits.len() as u64)?;
+        Ok(BloomFilter::<E, S> {
+            k: data.k,
+            bits: data.bits,
+            hasher: data.hasher,
+            marker: PhantomData,
+        })
+    }
+}
+
+/// Serializes a `BitVec` as its length and packed bytes.
+#[cfg(feature = "serde")]
+mod bits {
+    use bit_vec::BitVec;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(bits: &BitVec, serializer: S) -> Result<S::Ok, S::Error> {
+        (bits.len(), bits.to_bytes()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BitVec, D::Error> {
+        let (len, bytes): (usize, Vec<u8>) = Deserialize::deserialize(deserializer)?;
+        if bytes.len() != len.div_ceil(8) {
+            return Err(D::Error::custom("invalid bit vector length"));
+        }
+
+        let mut bits = BitVec::from_bytes(&bytes);
+        bits.truncate(len);
+        Ok(bits)
+    }
+}
+
+/// Returns the number of buckets and hash functions for a filter tuned for a population of `n`
+/// elements with the given upper bound of the probability of false positives, or an error if the
+/// probability isn't in the range `(0, 1)` or the filter would be too large. Probabilities below
+/// the range of the tables use the closed-form approximation instead.
+pub fn size_and_k(n: usize, max_false_pos_prob: f64) -> Result<(usize, usize), Error> {
+    if !(max_false_pos_prob > 0.0 && max_false_pos_prob < 1.0) {
+        return Err(Error::InvalidErrorRate(max_false_pos_prob));
+    }
+
+    let (bits, k) = match best_buckets_and_k(max_false_pos_prob) {
+        Some((buckets, k)) => (n.checked_mul(buckets), k),
+        None => {
+            let params = BloomParams::optimal(n.max(1), max_false_pos_prob)?;
+            (Some(params.bits()), params.k())
+        }
+    };
+    let len = bits
+        .and_then(|m| m.checked_add(20))
+        .ok_or(Error::InvalidParameter("too many elements"))?;
+    Ok((len, k))
+}
+
+/// Returns the number of buckets per element and hash functions from the tables for the given upper
+/// bound of the probability of false positives, or `None` if it's below the tables' range.
+pub(crate) fn best_buckets_and_k(max_false_pos_prob: f64) -> Option<(usize, usize)> {
     // Handle the trivial cases
     if max_false_pos_prob >= PROBS[MIN_BUCKETS][MIN_K] {
-        return (2, OPT_K[2]);
+        return Some((2, OPT_K[2]));
     }
 
     if max_false_pos_prob < PROBS[MAX_BUCKETS][MAX_K] {
-        return (MAX_K, MAX_BUCKETS);
+        return None;
     }
 
     // First find the minimal required number of buckets:
@@ -95,7 +488,7 @@ fn best_buckets_and_k(max_false_pos_prob: f64) -> (usize, usize) {
         k -= 1;
     }
 
-    (buckets, k)
+    Some((buckets, k))
 }
 
 static MAX_BUCKETS: usize = 15;
@@ -127,7 +520,7 @@ static OPT_K: [usize; 21] = [
     14,
 ];
 
-static PROBS: [&'static [f64]; 21] = [
+pub(crate) static PROBS: [&[f64]; 21] = [
     &[1.0],      // dummy row representing 0 buckets per element
     &[1.0, 1.0], // dummy row representing 1 buckets per element
     &[1.0, 0.393, 0.400],
@@ -184,13 +577,16 @@ static PROBS: [&'static [f64]; 21] = [
 mod test {
     use super::*;
 
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::BuildHasherDefault;
+
     #[test]
     fn insert_and_query() {
         let mut bf = BloomFilter::new(100, 0.01);
         bf.insert(100);
         bf.insert(400);
 
-        assert_eq!(bf.contains(&100), true);
+        assert!(bf.contains(&100));
     }
 
     #[test]
@@ -205,15 +601,232 @@ mod test {
             panic!("merge made no changes");
         }
 
-        assert_eq!(bf1.contains(&400), true);
+        assert!(bf1.contains(&400));
+    }
+
+    #[test]
+    fn introspection() {
+        let mut bf = BloomFilter::new(10_000, 0.01);
+        let (m, k) = size_and_k(10_000, 0.01).unwrap();
+        assert_eq!(bf.num_bits(), m);
+        assert_eq!(bf.k(), k);
+        assert_eq!(bf.count_ones(), 0);
+        assert_eq!(bf.cardinality(), 0.0);
+        assert_eq!(bf.false_pos_prob(), 0.0);
+
+        bf.insert(1);
+        assert_eq!(bf.count_ones(), k);
+
+        for i in 0..10_000 {
+            bf.insert(i);
+        }
+        let ones = (0..m).filter(|&i| bf.bits[i]).count();
+        assert_eq!(bf.count_ones(), ones);
+        assert_eq!(bf.fill_ratio(), ones as f64 / m as f64);
+        assert!((bf.cardinality() - 10_000.0).abs() < 10_000.0 * 0.02);
+        assert!(bf.false_pos_prob() > 0.005 && bf.false_pos_prob() < 0.02);
+
+        // Overfilling the filter pushes it past its designed probability.
+        for i in 10_000..30_000 {
+            bf.insert(i);
+        }
+        assert!((bf.cardinality() - 30_000.0).abs() < 30_000.0 * 0.02);
+        assert!(bf.false_pos_prob() > 0.1);
+
+        bf.clear();
+        assert_eq!(bf.count_ones(), 0);
+    }
+
+    #[test]
+    fn low_false_pos_prob() {
+        // Below the range of the tables, sizes follow the closed form rather than capping out.
+        let (m, k) = size_and_k(1000, 1e-5).unwrap();
+        assert_eq!(k, 17);
+        assert!(m > 20_000 && m < 25_000);
+
+        let (m, k) = size_and_k(1000, 1e-12).unwrap();
+        assert_eq!(k, 40);
+        assert!(m > 55_000 && m < 60_000);
+
+        let mut bf = BloomFilter::new(1000, 1e-12);
+        for i in 0..1000 {
+            bf.insert(i);
+        }
+        assert!(bf.false_pos_prob() < 1e-11);
+    }
+
+    #[test]
+    fn with_params() {
+        let mut bf = BloomFilter::with_params(1000, 3).unwrap();
+        assert_eq!(bf.num_bits(), 1000);
+        assert_eq!(bf.k(), 3);
+        bf.insert(1);
+        assert_eq!(bf.count_ones(), 3);
+        assert!(bf.contains(&1));
+
+        assert!(BloomFilter::<u64>::with_params(0, 3).is_err());
+        assert!(BloomFilter::<u64>::with_params(1000, 0).is_err());
+        assert!(BloomFilter::<u64>::with_params(10, 11).is_err());
+        assert!(BloomFilter::<u64>::with_params(10, 10).is_ok());
+    }
+
+    #[test]
+    fn intersect() {
+        let mut bf1 = BloomFilter::new(1000, 0.01);
+        let mut bf2 = BloomFilter::new(1000, 0.01);
+        for i in 0..200 {
+            bf1.insert(i);
+            bf2.insert(i + 100);
+        }
+
+        assert!(bf1.intersect(&bf2));
+        assert!((100..200).all(|i| bf1.contains(&i)));
+        assert!((200..300).filter(|i| bf1.contains(i)).count() < 10);
+        assert!(!bf2.intersect(&BloomFilter::from_bytes(&bf2.to_bytes()).unwrap()));
+
+        assert_eq!(
+            bf1.try_intersect(&BloomFilter::new(100, 0.01)),
+            Err(Error::Incompatible("different parameters"))
+        );
+    }
+
+    #[test]
+    fn similarity() {
+        let mut bf1 = BloomFilter::new(10_000, 0.01);
+        let mut bf2 = BloomFilter::new(10_000, 0.01);
+        assert_eq!(bf1.jaccard(&bf2), Ok(0.0));
+
+        for i in 0..3000 {
+            bf1.insert(i);
+            bf2.insert(i + 1000);
+        }
+
+        let union = bf1.union_cardinality(&bf2).unwrap();
+        let intersection = bf1.intersection_cardinality(&bf2).unwrap();
+        let jaccard = bf1.jaccard(&bf2).unwrap();
+        assert!((union - 4000.0).abs() < 4000.0 * 0.03, "{}", union);
+        assert!(
+            (intersection - 2000.0).abs() < 2000.0 * 0.05,
+            "{}",
+            intersection
+        );
+        assert!((jaccard - 0.5).abs() < 0.03, "{}", jaccard);
+
+        let mut disjoint = BloomFilter::new(10_000, 0.01);
+        for i in 5000..8000 {
+            disjoint.insert(i);
+        }
+        assert!(bf1.jaccard(&disjoint).unwrap() < 0.02);
+
+        assert_eq!(
+            bf1.jaccard(&BloomFilter::with_hasher(
+                10_000,
+                0.01,
+                SipBuildHasher::new(1, 2)
+            )),
+            Err(Error::HasherMismatch)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "mismatched hashers")]
+    fn merge_mismatched_hashers() {
+        let mut bf1 = BloomFilter::<u64>::new(100, 0.01);
+        let bf2 = BloomFilter::with_hasher(100, 0.01, SipBuildHasher::new(1, 2));
+        bf1.merge(&bf2);
+    }
+
+    #[test]
+    fn custom_hasher() {
+        let hasher = BuildHasherDefault::<DefaultHasher>::default();
+        let mut bf1 = BloomFilter::with_hasher(100, 0.01, hasher.clone());
+        bf1.insert(100);
+
+        let mut bf2 = BloomFilter::with_hasher(100, 0.01, hasher);
+        bf2.insert(400);
+        bf1.merge(&bf2);
+
+        assert!(bf1.contains(&100));
+        assert!(bf1.contains(&400));
+    }
+
+    #[test]
+    fn try_merge_mismatched_parameters() {
+        let mut bf1 = BloomFilter::<u64>::new(100, 0.01);
+        let bf2 = BloomFilter::new(100, 0.001);
+        assert_eq!(
+            bf1.try_merge(&bf2),
+            Err(Error::Incompatible("different parameters"))
+        );
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        for &p in &[0.0, -0.1, 1.0, f64::NAN] {
+            assert!(BloomFilter::<u64>::try_new(100, p).is_err());
+        }
+        assert_eq!(
+            BloomFilter::<u64>::try_new(usize::MAX, 0.01).err(),
+            Some(Error::InvalidParameter("too many elements"))
+        );
     }
 
     #[test]
     fn clear() {
         let mut bf = BloomFilter::new(100, 0.01);
         bf.insert(100);
-        assert_eq!(bf.contains(&100), true);
+        assert!(bf.contains(&100));
         bf.clear();
-        assert_eq!(bf.contains(&100), false);
+        assert!(!bf.contains(&100));
+    }
+
+    #[test]
+    fn to_and_from_bytes() {
+        let mut bf = BloomFilter::with_hasher(100, 0.01, SipBuildHasher::new(1, 2));
+        bf.insert(100);
+        bf.insert(400);
+
+        let bytes = bf.to_bytes();
+        let decoded = BloomFilter::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.k, bf.k);
+        assert_eq!(decoded.hasher, bf.hasher);
+        assert_eq!(decoded.bits, bf.bits);
+        assert!(decoded.contains(&100));
+        assert!(decoded.contains(&400));
+
+        assert_eq!(
+            BloomFilter::<i32>::from_bytes(&bytes[..bytes.len() - 1]).err(),
+            Some(Error::ChecksumMismatch)
+        );
+
+        let mut expensive = BloomFilter::<i32>::new(10, 0.01);
+        expensive.k = usize::MAX;
+        assert_eq!(
+            BloomFilter::<i32>::from_bytes(&expensive.to_bytes()).err(),
+            Some(Error::InvalidData("too many hash functions"))
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let mut bf = BloomFilter::new(100, 0.01);
+        bf.insert(100);
+
+        let json = ::serde_json::to_string(&bf).unwrap();
+        let decoded: BloomFilter<i32> = ::serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.bits, bf.bits);
+        assert!(decoded.contains(&100));
+
+        for json in &[
+            r#"{"k":1,"bits":[9,[0]],"hasher":{"k0":0,"k1":0}}"#,
+            r#"{"k":1,"bits":[0,[]],"hasher":{"k0":0,"k1":0}}"#,
+            r#"{"k":0,"bits":[8,[0]],"hasher":{"k0":0,"k1":0}}"#,
+            r#"{"k":9,"bits":[8,[0]],"hasher":{"k0":0,"k1":0}}"#,
+        ] {
+            assert!(::serde_json::from_str::<BloomFilter<i32>>(json).is_err());
+        }
+        let json = r#"{"k":8,"bits":[8,[0]],"hasher":{"k0":0,"k1":0}}"#;
+        assert!(::serde_json::from_str::<BloomFilter<i32>>(json).is_ok());
     }
 }
@@ -0,0 +1,306 @@
+use std::f64::consts::LN_2;
+
+use error::Error;
+
+/// The parameters of a Bloom filter: the number of elements it holds, the length of its bit
+/// vector, its number of hash functions, and the resulting probability of false positives.
+///
+/// Given any three, the fourth can be solved for with the closed-form approximation of the
+/// probability of false positives, `p = (1 - e^(-kn/m))^k`. Unlike the tables `BloomFilter::new`
+/// uses, this works for any number of elements and any probability.
+///
+/// ```
+/// use sketchy::{BloomFilter, BloomParams};
+///
+/// // Size a filter for 1M elements with a one in a billion chance of a false positive.
+/// let params = BloomParams::optimal(1_000_000, 1e-9).unwrap();
+/// assert_eq!(params.k(), 30);
+/// assert!(params.false_pos_prob() <= 1e-9);
+///
+/// let mut filter = BloomFilter::with_params(params.bits(), params.k()).unwrap();
+/// filter.insert("one");
+/// assert!(filter.contains(&"one"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomParams {
+    elements: usize,
+    bits: usize,
+    k: usize,
+    false_pos_prob: f64,
+}
+
+impl BloomParams {
+    /// Returns the smallest filter which holds `n` elements with at most the given probability of
+    /// false positives, using the optimal number of hash functions, `k = -log2(p)`. Returns an
+    /// error if `n` is zero, the probability isn't in the range `(0, 1)`, or the filter would be
+    /// too large.
+    pub fn optimal(n: usize, max_false_pos_prob: f64) -> Result<BloomParams, Error> {
+        validate_prob(max_false_pos_prob)?;
+        let k = (-max_false_pos_prob.log2()).round().max(1.0) as usize;
+        BloomParams::solve_bits(n, k, max_false_pos_prob)
+    }
+
+    /// Returns the number of bits a filter with `k` hash functions needs to hold `n` elements with
+    /// at most the given probability of false positives. Returns an error if `n` or `k` is zero,
+    /// the probability isn't in the range `(0, 1)`, or the filter would be too large.
+    pub fn solve_bits(n: usize, k: usize, max_false_pos_prob: f64) -> Result<BloomParams, Error> {
+        validate_elements(n)?;
+        validate_k(k)?;
+        validate_prob(max_false_pos_prob)?;
+
+        // m = -kn / ln(1 - p^(1/k))
+        let bits = (-(k as f64) * n as f64 / ln_1m_root(max_false_pos_prob, k)).ceil();
+        if bits >= usize::MAX as f64 {
+            return Err(Error::InvalidParameter("too many elements"));
+        }
+
+        // Rounding errors can leave the solution one bit off in either direction.
+        let bits = bits as usize;
+        let fits = |bits| BloomParams::new(n, bits, k).false_pos_prob <= max_false_pos_prob;
+        let bits = if !fits(bits) {
+            bits + 1
+        } else if bits > 1 && fits(bits - 1) {
+            bits - 1
+        } else {
+            bits
+        };
+        Ok(BloomParams::new(n, bits, k))
+    }
+
+    /// Returns the number of elements a filter of `bits` bits and `k` hash functions holds with at
+    /// most the given probability of false positives, which may be zero. Returns an error if
+    /// `bits` or `k` is zero or the probability isn't in the range `(0, 1)`.
+    pub fn solve_elements(
+        bits: usize,
+        k: usize,
+        max_false_pos_prob: f64,
+    ) -> Result<BloomParams, Error> {
+        validate_bits(bits)?;
+        validate_k(k)?;
+        validate_prob(max_false_pos_prob)?;
+
+        // n = -(m / k) ln(1 - p^(1/k))
+        let n = (-(bits as f64 / k as f64) * ln_1m_root(max_false_pos_prob, k)).floor() as usize;
+
+        // Rounding errors can leave the solution one element off in either direction.
+        let fits = |n| BloomParams::new(n, bits, k).false_pos_prob <= max_false_pos_prob;
+        let n = if n > 0 && !fits(n) {
+            n - 1
+        } else if n < usize::MAX && fits(n + 1) {
+            n + 1
+        } else {
+            n
+        };
+        Ok(BloomParams::new(n, bits, k))
+    }
+
+    /// Returns the smallest number of hash functions with which a filter of `bits` bits holds `n`
+    /// elements with at most the given probability of false positives. Returns an error if `n` or
+    /// `bits` is zero, the probability isn't in the range `(0, 1)`, or no number of hash functions
+    /// is enough.
+    pub fn solve_k(n: usize, bits: usize, max_false_pos_prob: f64) -> Result<BloomParams, Error> {
+        validate_elements(n)?;
+        validate_bits(bits)?;
+        validate_prob(max_false_pos_prob)?;
+
+        // The probability falls as k grows towards (m / n) ln 2, and rises after it.
+        let best = (bits as f64 / n as f64 * LN_2).ceil().max(1.0) as usize;
+        (1..=best)
+            .map(|k| BloomParams::new(n, bits, k))
+            .find(|params| params.false_pos_prob <= max_false_pos_prob)
+            .ok_or(Error::InvalidParameter("too few bits for the probability"))
+    }
+
+    /// Returns the probability of false positives of a filter of `bits` bits and `k` hash
+    /// functions holding `n` elements. Returns an error if `bits` or `k` is zero.
+    pub fn solve_false_pos_prob(n: usize, bits: usize, k: usize) -> Result<BloomParams, Error> {
+        validate_bits(bits)?;
+        validate_k(k)?;
+        Ok(BloomParams::new(n, bits, k))
+    }
+
+    fn new(n: usize, bits: usize, k: usize) -> BloomParams {
+        // p = (1 - e^(-kn/m))^k
+        let fill = -(-(k as f64) * n as f64 / bits as f64).exp_m1();
+        BloomParams {
+            elements: n,
+            bits,
+            k,
+            false_pos_prob: fill.powf(k as f64),
+        }
+    }
+
+    /// Returns the number of elements in the filter.
+    pub fn elements(&self) -> usize {
+        self.elements
+    }
+
+    /// Returns the length of the filter's bit vector.
+    pub fn bits(&self) -> usize {
+        self.bits
+    }
+
+    /// Returns the number of hash functions.
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// Returns the probability of false positives once the filter holds `elements()` elements.
+    pub fn false_pos_prob(&self) -> f64 {
+        self.false_pos_prob
+    }
+}
+
+/// Returns `ln(1 - p^(1/k))`, avoiding the loss of precision when `p^(1/k)` is close to zero or
+/// one.
+fn ln_1m_root(p: f64, k: usize) -> f64 {
+    (-(p.ln() / k as f64).exp_m1()).ln()
+}
+
+fn validate_elements(n: usize) -> Result<(), Error> {
+    if n == 0 {
+        return Err(Error::InvalidParameter(
+            "number of elements must be positive",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_bits(bits: usize) -> Result<(), Error> {
+    if bits == 0 {
+        return Err(Error::InvalidParameter("number of bits must be positive"));
+    }
+    Ok(())
+}
+
+fn validate_k(k: usize) -> Result<(), Error> {
+    if k == 0 {
+        return Err(Error::InvalidParameter(
+            "number of hash functions must be positive",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_prob(p: f64) -> Result<(), Error> {
+    if !(p > 0.0 && p < 1.0) {
+        return Err(Error::InvalidErrorRate(p));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bloomfilter::{best_buckets_and_k, BloomFilter, PROBS};
+
+    #[test]
+    fn optimal() {
+        let params = BloomParams::optimal(10_000, 0.01).unwrap();
+        assert_eq!(params.elements(), 10_000);
+        assert_eq!(params.k(), 7);
+        assert!(params.false_pos_prob() <= 0.01);
+        assert!(params.bits() > 95_000 && params.bits() < 96_000);
+
+        for &p in &[0.5, 0.1, 1e-3, 1e-5, 1e-9, 1e-15, 1e-30, 1e-100] {
+            let params = BloomParams::optimal(1000, p).unwrap();
+            assert!(params.false_pos_prob() <= p, "p={}: {:?}", p, params);
+            assert!(params.false_pos_prob() > p * 0.99, "p={}: {:?}", p, params);
+
+            let bits_per_element = params.bits() as f64 / 1000.0;
+            let ideal = -p.log2() / LN_2;
+            assert!(bits_per_element < ideal * 1.05, "p={}: {:?}", p, params);
+        }
+
+        assert!(BloomParams::optimal(0, 0.01).is_err());
+        assert!(BloomParams::optimal(10, 0.0).is_err());
+        assert!(BloomParams::optimal(10, 1.0).is_err());
+        assert!(BloomParams::optimal(usize::MAX, 1e-9).is_err());
+    }
+
+    #[test]
+    fn matches_tables() {
+        // The tables are rounded to three significant figures.
+        for (buckets, row) in PROBS.iter().enumerate().skip(2) {
+            for (k, &expected) in row.iter().enumerate().skip(1) {
+                let p = BloomParams::solve_false_pos_prob(100_000, buckets * 100_000, k)
+                    .unwrap()
+                    .false_pos_prob();
+                assert!(
+                    (p - expected).abs() < expected * 0.01 + 5e-5,
+                    "{} {}",
+                    p,
+                    expected
+                );
+            }
+        }
+
+        // Within the tables, the optimal filter is never larger than the one `new` builds.
+        for &p in &[0.1, 0.05, 0.01, 0.005, 0.001] {
+            let (buckets, _) = best_buckets_and_k(p).unwrap();
+            assert!(BloomParams::optimal(100_000, p).unwrap().bits() <= buckets * 100_000);
+        }
+    }
+
+    #[test]
+    fn solve_each() {
+        let params = BloomParams::solve_false_pos_prob(5000, 80_000, 6).unwrap();
+        let p = params.false_pos_prob();
+        assert!(p > 0.0009 && p < 0.001, "{}", p);
+
+        // The solved parameters are the tightest which meet the bound.
+        let bits = BloomParams::solve_bits(5000, 6, p).unwrap();
+        assert_eq!(bits.bits(), 80_000);
+        assert!(bits.false_pos_prob() <= p);
+        assert!(
+            BloomParams::solve_false_pos_prob(5000, bits.bits() - 1, 6)
+                .unwrap()
+                .false_pos_prob()
+                > p
+        );
+
+        let elements = BloomParams::solve_elements(80_000, 6, p).unwrap();
+        assert_eq!(elements.elements(), 5000);
+        assert!(elements.false_pos_prob() <= p);
+        assert!(
+            BloomParams::solve_false_pos_prob(elements.elements() + 1, 80_000, 6)
+                .unwrap()
+                .false_pos_prob()
+                > p
+        );
+
+        // Fewer hash functions are enough for a looser bound.
+        assert_eq!(BloomParams::solve_k(5000, 80_000, p).unwrap().k(), 6);
+        assert!(BloomParams::solve_k(5000, 80_000, 0.01).unwrap().k() < 6);
+        assert_eq!(BloomParams::solve_k(5000, 80_000, 0.5).unwrap().k(), 1);
+        assert!(BloomParams::solve_k(5000, 80_000, 1e-9).is_err());
+
+        assert_eq!(
+            BloomParams::solve_elements(80_000, 6, 1e-300)
+                .unwrap()
+                .elements(),
+            0
+        );
+        assert_eq!(
+            BloomParams::solve_false_pos_prob(0, 100, 3)
+                .unwrap()
+                .false_pos_prob(),
+            0.0
+        );
+        assert!(BloomParams::solve_bits(5000, 0, 0.01).is_err());
+        assert!(BloomParams::solve_elements(0, 6, 0.01).is_err());
+        assert!(BloomParams::solve_k(5000, 80_000, 1.5).is_err());
+    }
+
+    #[test]
+    fn low_false_pos_prob() {
+        let n = 20_000;
+        let params = BloomParams::optimal(n, 1e-6).unwrap();
+        let mut bf = BloomFilter::with_params(params.bits(), params.k()).unwrap();
+        for i in 0..n {
+            bf.insert(i);
+        }
+        assert!((n..n + 300_000).filter(|i| bf.contains(i)).count() < 5);
+    }
+}
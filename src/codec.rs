@@ -0,0 +1,409 @@
+//! The binary format shared by all sketches.
+//!
+//! Every encoded sketch starts with a five-byte header: the magic bytes `SK`, a type tag, the
+//! format version, and the hash scheme used to map elements to the sketch's state. The header is
+//! followed by the sketch's parameters and state, and the whole thing is terminated by a
+//! little-endian CRC-32 of all the preceding bytes. Unless otherwise noted, integers are encoded as
+//! unsigned LEB128 varints.
+
+use std::mem;
+
+use error::Error;
+
+/// The magic bytes at the start of every encoded sketch.
+const MAGIC: [u8; 2] = *b"SK";
+
+/// The current version of the format.
+const VERSION: u8 = 1;
+
+/// The length of the header, in bytes.
+const HEADER_LEN: usize = 5;
+
+/// The length of the checksum, in bytes.
+const CHECKSUM_LEN: usize = 4;
+
+/// The type tag of a `BloomFilter`.
+pub const BLOOM_FILTER: u8 = 1;
+
+/// The type tag of a `CountMinSketch`.
+pub const COUNT_MIN_SKETCH: u8 = 2;
+
+/// The type tag of a `HyperLogLog`.
+pub const HYPERLOGLOG: u8 = 3;
+
+/// The type tag of a `ReservoirSample`.
+pub const RESERVOIR_SAMPLE: u8 = 4;
+
+/// The type tag of a `TopK`.
+pub const TOP_K: u8 = 5;
+
+/// The type tag of a `SlidingHyperLogLog`.
+pub const SLIDING_HYPERLOGLOG: u8 = 6;
+
+/// The type tag of a `CountingBloomFilter`.
+pub const COUNTING_BLOOM_FILTER: u8 = 7;
+
+/// The type tag of a `ScalableBloomFilter`.
+pub const SCALABLE_BLOOM_FILTER: u8 = 8;
+
+/// The type tag of a `BlockedBloomFilter`.
+pub const BLOCKED_BLOOM_FILTER: u8 = 9;
+
+/// The type tag of a `CuckooFilter`.
+pub const CUCKOO_FILTER: u8 = 10;
+
+/// The type tag of an `XorFilter8`.
+pub const XOR_FILTER8: u8 = 11;
+
+/// The type tag of a `BinaryFuseFilter8`.
+pub const BINARY_FUSE_FILTER8: u8 = 12;
+
+/// The type tag of a `BinaryFuseFilter16`.
+pub const BINARY_FUSE_FILTER16: u8 = 13;
+
+/// The type tag of a `QuotientFilter`.
+pub const QUOTIENT_FILTER: u8 = 14;
+
+/// The type tag of a `RibbonFilter`.
+pub const RIBBON_FILTER: u8 = 15;
+
+/// The hash scheme of sketches which don't hash their elements.
+pub const HASH_NONE: u8 = 0;
+
+/// The hash scheme of sketches which hash their elements with SipHash-2-4. The 128-bit key is
+/// encoded as two little-endian `u64`s at the start of the sketch's parameters.
+pub const HASH_SIPHASH24: u8 = 2;
+
+/// A type which can be encoded as part of a sketch which stores elements, like a
+/// `ReservoirSample` or a `TopK`.
+pub trait Codec: Sized {
+    /// Appends the encoded value to the buffer.
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    /// Decodes a value from the front of the input, advancing the input past it.
+    fn decode(input: &mut &[u8]) -> Result<Self, Error>;
+}
+
+macro_rules! unsigned_codec {
+    ($($t:ty),*) => {
+        $(
+            impl Codec for $t {
+                fn encode(&self, buf: &mut Vec<u8>) {
+                    put_varint(buf, *self as u64);
+                }
+
+                fn decode(input: &mut &[u8]) -> Result<$t, Error> {
+                    let v = get_varint(input)?;
+                    if v > <$t>::MAX as u64 {
+                        return Err(Error::InvalidData("integer out of range"));
+                    }
+                    Ok(v as $t)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! signed_codec {
+    ($($t:ty),*) => {
+        $(
+            impl Codec for $t {
+                fn encode(&self, buf: &mut Vec<u8>) {
+                    // Zig-zag encode the value so small negative values stay small.
+                    let v = *self as i64;
+                    put_varint(buf, ((v << 1) ^ (v >> 63)) as u64);
+                }
+
+                fn decode(input: &mut &[u8]) -> Result<$t, Error> {
+                    let v = get_varint(input)?;
+                    let v = ((v >> 1) as i64) ^ -((v & 1) as i64);
+                    if v < <$t>::MIN as i64 || v > <$t>::MAX as i64 {
+                        return Err(Error::InvalidData("integer out of range"));
+                    }
+                    Ok(v as $t)
+                }
+            }
+        )*
+    };
+}
+
+unsigned_codec!(u8, u16, u32, u64, usize);
+signed_codec!(i8, i16, i32, i64, isize);
+
+impl Codec for bool {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(*self as u8);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<bool, Error> {
+        match get_u8(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::InvalidData("invalid bool")),
+        }
+    }
+}
+
+impl Codec for char {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_varint(buf, u64::from(*self as u32));
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<char, Error> {
+        let v = u32::decode(input)?;
+        ::std::char::from_u32(v).ok_or(Error::InvalidData("invalid char"))
+    }
+}
+
+impl Codec for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_varint(buf, self.len() as u64);
+        buf.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<String, Error> {
+        let len = get_len(input)?;
+        let bytes = get_bytes(input, len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::InvalidData("invalid string"))
+    }
+}
+
+/// Returns a buffer containing the header of a sketch with the given type tag and hash scheme.
+pub fn header(tag: u8, hash: u8) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&MAGIC);
+    buf.push(tag);
+    buf.push(VERSION);
+    buf.push(hash);
+    buf
+}
+
+/// Appends the checksum to an encoded sketch.
+pub fn finish(mut buf: Vec<u8>) -> Vec<u8> {
+    let crc = crc32(&buf);
+    buf.extend_from_slice(&crc.to_le_bytes());
+    buf
+}
+
+/// Checks the header and checksum of an encoded sketch, returning the encoded parameters and state
+/// which follow the header.
+pub fn open(bytes: &[u8], tag: u8, hash: u8) -> Result<&[u8], Error> {
+    if bytes.len() < HEADER_LEN + CHECKSUM_LEN {
+        return Err(Error::Truncated);
+    }
+
+    if bytes[..2] != MAGIC {
+        return Err(Error::InvalidMagic);
+    }
+
+    let (data, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+    let mut expected = [0; CHECKSUM_LEN];
+    expected.copy_from_slice(checksum);
+    if crc32(data) != u32::from_le_bytes(expected) {
+        return Err(Error::ChecksumMismatch);
+    }
+
+    if data[2] != tag {
+        return Err(Error::UnexpectedType(data[2]));
+    }
+
+    if data[3] != VERSION {
+        return Err(Error::UnsupportedVersion(data[3]));
+    }
+
+    if data[4] != hash {
+        return Err(Error::UnsupportedHash(data[4]));
+    }
+
+    Ok(&data[HEADER_LEN..])
+}
+
+/// Returns an error if any input remains after decoding a sketch.
+pub fn close(input: &[u8]) -> Result<(), Error> {
+    if input.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::InvalidData("trailing bytes"))
+    }
+}
+
+/// Appends a varint to the buffer.
+pub fn put_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+/// Appends a little-endian `f64` to the buffer.
+pub fn put_f64(buf: &mut Vec<u8>, v: f64) {
+    buf.extend_from_slice(&v.to_bits().to_le_bytes());
+}
+
+/// Appends a little-endian `u64` to the buffer.
+pub fn put_u64(buf: &mut Vec<u8>, v: u64) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+/// Decodes a byte.
+pub fn get_u8(input: &mut &[u8]) -> Result<u8, Error> {
+    Ok(get_bytes(input, 1)?[0])
+}
+
+/// Decodes a varint.
+pub fn get_varint(input: &mut &[u8]) -> Result<u64, Error> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let b = get_u8(input)?;
+        if shift == 63 && b > 1 {
+            return Err(Error::InvalidData("varint overflow"));
+        }
+
+        v |= u64::from(b & 0x7f) << shift;
+        if b & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    Err(Error::InvalidData("varint overflow"))
+}
+
+/// Decodes a varint which is the length of something with at least one byte per element, returning
+/// an error if the input is too short to contain it. This prevents corrupt lengths from causing
+/// huge allocations.
+pub fn get_len(input: &mut &[u8]) -> Result<usize, Error> {
+    let len = get_varint(input)?;
+    if len > input.len() as u64 {
+        return Err(Error::Truncated);
+    }
+    Ok(len as usize)
+}
+
+/// Decodes a little-endian `f64`.
+pub fn get_f64(input: &mut &[u8]) -> Result<f64, Error> {
+    Ok(f64::from_bits(get_u64(input)?))
+}
+
+/// Decodes a little-endian `u64`.
+pub fn get_u64(input: &mut &[u8]) -> Result<u64, Error> {
+    let mut b = [0; mem::size_of::<u64>()];
+    b.copy_from_slice(get_bytes(input, mem::size_of::<u64>())?);
+    Ok(u64::from_le_bytes(b))
+}
+
+/// Decodes `n` raw bytes.
+pub fn get_bytes<'a>(input: &mut &'a [u8], n: usize) -> Result<&'a [u8], Error> {
+    if input.len() < n {
+        return Err(Error::Truncated);
+    }
+    let (bytes, rest) = input.split_at(n);
+    *input = rest;
+    Ok(bytes)
+}
+
+/// Returns the CRC-32 (IEEE) checksum of the given bytes.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= u32::from(b);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn checksum() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn varints() {
+        for &v in &[0, 1, 127, 128, 300, 1 << 35, u64::MAX] {
+            let mut buf = Vec::new();
+            put_varint(&mut buf, v);
+
+            let mut input = &buf[..];
+            assert_eq!(get_varint(&mut input), Ok(v));
+            assert!(input.is_empty());
+        }
+
+        assert_eq!(get_varint(&mut &[0x80][..]), Err(Error::Truncated));
+        assert_eq!(
+            get_varint(&mut &[0xff; 10][..]),
+            Err(Error::InvalidData("varint overflow"))
+        );
+    }
+
+    #[test]
+    fn elements() {
+        let mut buf = Vec::new();
+        (-300i16).encode(&mut buf);
+        i64::MIN.encode(&mut buf);
+        u32::MAX.encode(&mut buf);
+        true.encode(&mut buf);
+        'λ'.encode(&mut buf);
+        "whee".to_string().encode(&mut buf);
+        300u64.encode(&mut buf);
+
+        let mut input = &buf[..];
+        assert_eq!(i16::decode(&mut input), Ok(-300));
+        assert_eq!(i64::decode(&mut input), Ok(i64::MIN));
+        assert_eq!(u32::decode(&mut input), Ok(u32::MAX));
+        assert_eq!(bool::decode(&mut input), Ok(true));
+        assert_eq!(char::decode(&mut input), Ok('λ'));
+        assert_eq!(String::decode(&mut input), Ok("whee".to_string()));
+        assert_eq!(
+            u8::decode(&mut input),
+            Err(Error::InvalidData("integer out of range"))
+        );
+    }
+
+    #[test]
+    fn envelope() {
+        let mut buf = header(TOP_K, HASH_SIPHASH24);
+        buf.push(42);
+        let bytes = finish(buf);
+
+        assert_eq!(open(&bytes, TOP_K, HASH_SIPHASH24), Ok(&[42][..]));
+        assert_eq!(
+            open(&bytes, BLOOM_FILTER, HASH_SIPHASH24),
+            Err(Error::UnexpectedType(TOP_K))
+        );
+        assert_eq!(
+            open(&bytes, TOP_K, HASH_NONE),
+            Err(Error::UnsupportedHash(HASH_SIPHASH24))
+        );
+        assert_eq!(
+            open(&bytes[..8], TOP_K, HASH_SIPHASH24),
+            Err(Error::Truncated)
+        );
+
+        let mut corrupt = bytes.clone();
+        corrupt[5] ^= 1;
+        assert_eq!(
+            open(&corrupt, TOP_K, HASH_SIPHASH24),
+            Err(Error::ChecksumMismatch)
+        );
+
+        let mut corrupt = bytes.clone();
+        corrupt[0] = b'X';
+        assert_eq!(
+            open(&corrupt, TOP_K, HASH_SIPHASH24),
+            Err(Error::InvalidMagic)
+        );
+
+        let mut buf = header(TOP_K, HASH_SIPHASH24);
+        buf[3] = VERSION + 1;
+        assert_eq!(
+            open(&finish(buf), TOP_K, HASH_SIPHASH24),
+            Err(Error::UnsupportedVersion(VERSION + 1))
+        );
+    }
+}
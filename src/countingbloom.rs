@@ -0,0 +1,508 @@
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::mem;
+
+use bloomfilter::size_and_k;
+use codec;
+use error::Error;
+use hash::{fingerprint, indexes, SipBuildHasher};
+use packed::PackedVec;
+use sketch::{Estimator, Mergeable, Sketch};
+
+/// A counting Bloom filter is a Bloom filter which replaces each bit with a small counter, which
+/// allows elements to be removed as well as inserted.
+///
+/// Counters saturate at their maximum value instead of overflowing. A saturated counter is never
+/// decremented, since the true number of elements mapped to it is no longer known, so false
+/// negatives remain impossible as long as only inserted elements are removed.
+///
+/// ```
+/// use sketchy::CountingBloomFilter;
+///
+/// let mut filter = CountingBloomFilter::new(100_000, 0.01);
+///
+/// filter.insert("one");
+/// filter.insert("two");
+/// assert!(filter.contains(&"one"));
+///
+/// assert!(filter.remove(&"one"));
+/// assert!(!filter.contains(&"one"));
+/// ```
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        bound(
+            serialize = "S: ::serde::Serialize",
+            deserialize = "S: ::serde::Deserialize<'de>"
+        ),
+        try_from = "CountingBloomFilterData<S>"
+    )
+)]
+pub struct CountingBloomFilter<E, S = SipBuildHasher> {
+    k: usize,
+    counters: PackedVec,
+    hasher: S,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    marker: PhantomData<E>,
+}
+
+/// The default width of the counters, in bits.
+const DEFAULT_COUNTER_WIDTH: usize = 4;
+
+impl<E: Hash> CountingBloomFilter<E> {
+    /// Creates a new `CountingBloomFilter` with 4-bit counters, tuned for a population of `n`
+    /// elements with the given upper bound of the probability of false positives.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the probability isn't in the range `(0, 1)` or the filter would be too large.
+    pub fn new(n: usize, max_false_pos_prob: f64) -> CountingBloomFilter<E> {
+        match CountingBloomFilter::try_new(n, max_false_pos_prob) {
+            Ok(cbf) => cbf,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Creates a new `CountingBloomFilter` with 4-bit counters, tuned for a population of `n`
+    /// elements with the given upper bound of the probability of false positives, or returns an
+    /// error if the probability isn't in the range `(0, 1)` or the filter would be too large.
+    pub fn try_new(n: usize, max_false_pos_prob: f64) -> Result<CountingBloomFilter<E>, Error> {
+        CountingBloomFilter::with_counter_width(n, max_false_pos_prob, DEFAULT_COUNTER_WIDTH)
+    }
+
+    /// Creates a new `CountingBloomFilter` with counters of the given width in bits, tuned for a
+    /// population of `n` elements with the given upper bound of the probability of false
+    /// positives. Returns an error if the width isn't 4, 8, or 16, the probability isn't in the
+    /// range `(0, 1)`, or the filter would be too large.
+    pub fn with_counter_width(
+        n: usize,
+        max_false_pos_prob: f64,
+        width: usize,
+    ) -> Result<CountingBloomFilter<E>, Error> {
+        CountingBloomFilter::with_counter_width_and_hasher(
+            n,
+            max_false_pos_prob,
+            width,
+            SipBuildHasher::default(),
+        )
+    }
+
+    /// Returns the filter encoded in a compact, versioned binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = codec::header(codec::COUNTING_BLOOM_FILTER, codec::HASH_SIPHASH24);
+        self.hasher.encode(&mut buf);
+        codec::put_varint(&mut buf, self.k as u64);
+        buf.push(self.counters.width() as u8);
+        codec::put_varint(&mut buf, self.counters.len() as u64);
+        for &w in self.counters.words() {
+            codec::put_u64(&mut buf, w);
+        }
+        codec::finish(buf)
+    }
+
+    /// Decodes a filter from the format produced by `to_bytes`, returning an error if the data is
+    /// corrupt or isn't an encoded `CountingBloomFilter`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<CountingBloomFilter<E>, Error> {
+        let mut input = codec::open(bytes, codec::COUNTING_BLOOM_FILTER, codec::HASH_SIPHASH24)?;
+        let hasher = SipBuildHasher::decode(&mut input)?;
+        let k = codec::get_varint(&mut input)? as usize;
+        let width = codec::get_u8(&mut input)? as usize;
+        let len = codec::get_varint(&mut input)? as usize;
+        check_params(k, width, len)?;
+
+        // Each word takes eight bytes.
+        match len.checked_mul(width) {
+            Some(bits) if bits.div_ceil(64) <= input.len() / mem::size_of::<u64>() => {}
+            _ => return Err(Error::Truncated),
+        }
+
+        let mut counters = PackedVec::new(width, len);
+        for w in counters.words_mut() {
+            *w = codec::get_u64(&mut input)?;
+        }
+        codec::close(input)?;
+
+        Ok(CountingBloomFilter::<E> {
+            k,
+            counters,
+            hasher,
+            marker: PhantomData,
+        })
+    }
+}
+
+impl<E: Hash, S: BuildHasher> CountingBloomFilter<E, S> {
+    /// Creates a new `CountingBloomFilter` with counters of the given width in bits, tuned for a
+    /// population of `n` elements with the given upper bound of the probability of false
+    /// positives, which hashes elements with the given hasher. Returns an error if the width isn't
+    /// 4, 8, or 16, the probability isn't in the range `(0, 1)`, or the filter would be too large.
+    pub fn with_counter_width_and_hasher(
+        n: usize,
+        max_false_pos_prob: f64,
+        width: usize,
+        hasher: S,
+    ) -> Result<CountingBloomFilter<E, S>, Error> {
+        if !valid_width(width) {
+            return Err(Error::InvalidParameter("counter width must be 4, 8, or 16"));
+        }
+
+        let (len, k) = size_and_k(n, max_false_pos_prob)?;
+        if len.checked_mul(width).is_none() {
+            return Err(Error::InvalidParameter("too many elements"));
+        }
+
+        Ok(CountingBloomFilter::<E, S> {
+            k,
+            counters: PackedVec::new(width, len),
+            hasher,
+            marker: PhantomData,
+        })
+    }
+
+    /// Returns the hasher used to hash elements.
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    /// Returns the width of the counters, in bits.
+    pub fn counter_width(&self) -> usize {
+        self.counters.width()
+    }
+
+    /// Adds a value to the set.
+    pub fn insert(&mut self, e: E) {
+        let max = self.counters.max_value();
+        for i in indexes(&e, self.counters.len(), &self.hasher).take(self.k) {
+            let c = self.counters.get(i);
+            if c < max {
+                self.counters.set(i, c + 1);
+            }
+        }
+    }
+
+    /// Returns `true` if the set probably contains the given element.
+    pub fn contains(&self, e: &E) -> bool {
+        indexes(e, self.counters.len(), &self.hasher)
+            .take(self.k)
+            .all(|i| self.counters.get(i) > 0)
+    }
+
+    /// Returns an upper bound of the number of times the given element was inserted, if none of its
+    /// counters have saturated.
+    pub fn count(&self, e: &E) -> u64 {
+        indexes(e, self.counters.len(), &self.hasher)
+            .take(self.k)
+            .map(|i| self.counters.get(i))
+            .min()
+            .unwrap()
+    }
+
+    /// Removes a value from the set. Returns `true` if the set probably contained the value, or
+    /// `false`, leaving the set unmodified, if it definitely didn't.
+    ///
+    /// Removing an element which was never inserted but is a false positive corrupts the counters
+    /// of other elements, and may cause false negatives.
+    pub fn remove(&mut self, e: &E) -> bool {
+        if !self.contains(e) {
+            return false;
+        }
+
+        let max = self.counters.max_value();
+        for i in indexes(e, self.counters.len(), &self.hasher).take(self.k) {
+            let c = self.counters.get(i);
+            if c < max {
+                self.counters.set(i, c - 1);
+            }
+        }
+        true
+    }
+
+    /// Merges the contents of the given `CountingBloomFilter` into `self` by adding their
+    /// counters, saturating at the maximum counter value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the filters have different parameters or hashers.
+    pub fn merge(&mut self, other: &CountingBloomFilter<E, S>) {
+        if let Err(e) = self.try_merge(other) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Merges the contents of the given `CountingBloomFilter` into `self` by adding their
+    /// counters, or returns an error if the filters have different parameters or hashers.
+    pub fn try_merge(&mut self, other: &CountingBloomFilter<E, S>) -> Result<(), Error> {
+        self.check_compatible(other)?;
+        let max = self.counters.max_value();
+        for (i, c) in other.counters.iter().enumerate() {
+            if c > 0 {
+                let sum = self.counters.get(i).saturating_add(c);
+                self.counters.set(i, sum.min(max));
+            }
+        }
+        Ok(())
+    }
+
+    /// Resets all counters to zero, removing all elements from the set.
+    pub fn clear(&mut self) {
+        for w in self.counters.words_mut() {
+            *w = 0;
+        }
+    }
+
+    /// Returns the approximate amount of memory used by the filter, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>() + self.counters.memory_usage()
+    }
+}
+
+impl<E: Hash, S: BuildHasher> Sketch<E> for CountingBloomFilter<E, S> {
+    fn insert(&mut self, e: E) {
+        CountingBloomFilter::insert(self, e)
+    }
+
+    fn clear(&mut self) {
+        CountingBloomFilter::clear(self)
+    }
+
+    fn memory_usage(&self) -> usize {
+        CountingBloomFilter::memory_usage(self)
+    }
+}
+
+impl<E: Hash, S: BuildHasher> Mergeable for CountingBloomFilter<E, S> {
+    fn check_compatible(&self, other: &Self) -> Result<(), Error> {
+        if self.k != other.k
+            || self.counters.len() != other.counters.len()
+            || self.counters.width() != other.counters.width()
+        {
+            return Err(Error::Incompatible("different parameters"));
+        }
+
+        if fingerprint(&self.hasher) != fingerprint(&other.hasher) {
+            return Err(Error::HasherMismatch);
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: &Self) -> Result<(), Error> {
+        self.try_merge(other)
+    }
+}
+
+impl<E: Hash, S: BuildHasher> Estimator<E> for CountingBloomFilter<E, S> {
+    type Estimate = bool;
+
+    fn estimate(&self, e: &E) -> bool {
+        self.contains(e)
+    }
+}
+
+fn valid_width(width: usize) -> bool {
+    width == 4 || width == 8 || width == 16
+}
+
+/// Checks the parameters of a decoded or deserialized filter. The number of hash functions is
+/// bounded by the number of counters, as each query computes `k` hashes.
+fn check_params(k: usize, width: usize, len: usize) -> Result<(), Error> {
+    if k == 0 || len == 0 {
+        return Err(Error::InvalidData("empty filter"));
+    }
+
+    if !valid_width(width) {
+        return Err(Error::InvalidData("invalid counter width"));
+    }
+
+    if k > len {
+        return Err(Error::InvalidData("too many hash functions"));
+    }
+    Ok(())
+}
+
+/// The fields of a deserialized `CountingBloomFilter`, which are checked before use.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct CountingBloomFilterData<S> {
+    k: usize,
+    counters: PackedVec,
+    hasher: S,
+}
+
+#[cfg(feature = "serde")]
+impl<E, S> ::std::convert::TryFrom<CountingBloomFilterData<S>> for CountingBloomFilter<E, S> {
+    type Error = Error;
+
+    fn try_from(data: CountingBloomFilterData<S>) -> Result<CountingBloomFilter<E, S>, Error> {
+        check_params(data.k, data.counters.width(), data.counters.len())?;
+        Ok(CountingBloomFilter {
+            k: data.k,
+            counters: data.counters,
+            hasher: data.hasher,
+            marker: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bloomfilter::BloomFilter;
+
+    #[test]
+    fn insert_and_remove() {
+        let mut cbf = CountingBloomFilter::new(100, 0.01);
+        cbf.insert(100);
+        cbf.insert(400);
+        cbf.insert(400);
+
+        assert!(cbf.contains(&100));
+        assert!(cbf.count(&400) >= 2);
+
+        assert!(cbf.remove(&100));
+        assert!(!cbf.contains(&100));
+
+        assert!(cbf.remove(&400));
+        assert!(cbf.contains(&400));
+        assert!(cbf.remove(&400));
+        assert!(!cbf.contains(&400));
+    }
+
+    #[test]
+    fn remove_absent() {
+        let mut cbf = CountingBloomFilter::new(100, 0.01);
+        cbf.insert(100);
+
+        assert!(!cbf.remove(&200));
+        assert!(cbf.contains(&100));
+    }
+
+    #[test]
+    fn saturating_counters() {
+        let mut cbf = CountingBloomFilter::new(100, 0.01);
+        for _ in 0..20 {
+            cbf.insert(100);
+        }
+        assert_eq!(cbf.count(&100), 15);
+
+        // Saturated counters are never decremented, so the element can't be removed.
+        for _ in 0..20 {
+            assert!(cbf.remove(&100));
+        }
+        assert!(cbf.contains(&100));
+    }
+
+    #[test]
+    fn counter_widths() {
+        for &width in &[4, 8, 16] {
+            let mut cbf = CountingBloomFilter::with_counter_width(100, 0.01, width).unwrap();
+            assert_eq!(cbf.counter_width(), width);
+            for _ in 0..300 {
+                cbf.insert("one");
+            }
+            assert_eq!(cbf.count(&"one"), 300.min((1 << width) - 1));
+        }
+
+        for &width in &[0, 1, 7, 32] {
+            assert_eq!(
+                CountingBloomFilter::<u64>::with_counter_width(100, 0.01, width).err(),
+                Some(Error::InvalidParameter("counter width must be 4, 8, or 16"))
+            );
+        }
+        assert!(CountingBloomFilter::<u64>::try_new(100, 1.0).is_err());
+    }
+
+    #[test]
+    fn matches_bloom_filter() {
+        let mut bf = BloomFilter::new(1000, 0.01);
+        let mut cbf = CountingBloomFilter::new(1000, 0.01);
+        for i in 0..1000 {
+            bf.insert(i);
+            cbf.insert(i);
+        }
+
+        for i in 0..10_000 {
+            assert_eq!(cbf.contains(&i), bf.contains(&i));
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let mut one = CountingBloomFilter::new(100, 0.01);
+        one.insert(100);
+        one.insert(400);
+
+        let mut two = CountingBloomFilter::new(100, 0.01);
+        two.insert(400);
+
+        one.merge(&two);
+        assert!(one.contains(&100));
+        assert!(one.count(&400) >= 2);
+
+        let three = CountingBloomFilter::with_counter_width(100, 0.01, 8).unwrap();
+        assert_eq!(
+            one.try_merge(&three),
+            Err(Error::Incompatible("different parameters"))
+        );
+    }
+
+    #[test]
+    fn clear() {
+        let mut cbf = CountingBloomFilter::new(100, 0.01);
+        cbf.insert(100);
+        cbf.clear();
+        assert!(!cbf.contains(&100));
+    }
+
+    #[test]
+    fn to_and_from_bytes() {
+        let mut cbf = CountingBloomFilter::with_counter_width(100, 0.01, 8).unwrap();
+        cbf.insert(100);
+        cbf.insert(400);
+        cbf.insert(400);
+
+        let bytes = cbf.to_bytes();
+        let decoded = CountingBloomFilter::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.k, cbf.k);
+        assert_eq!(decoded.counters, cbf.counters);
+        assert!(decoded.contains(&100));
+        assert!(decoded.count(&400) >= 2);
+
+        let mut expensive = CountingBloomFilter::<i32>::new(10, 0.01);
+        expensive.k = usize::MAX;
+        assert_eq!(
+            CountingBloomFilter::<i32>::from_bytes(&expensive.to_bytes()).err(),
+            Some(Error::InvalidData("too many hash functions"))
+        );
+
+        assert_eq!(
+            CountingBloomFilter::<u64>::from_bytes(&BloomFilter::<u64>::new(100, 0.1).to_bytes())
+                .err(),
+            Some(Error::UnexpectedType(codec::BLOOM_FILTER))
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let mut cbf = CountingBloomFilter::new(100, 0.01);
+        cbf.insert(100);
+
+        let json = ::serde_json::to_string(&cbf).unwrap();
+        let decoded: CountingBloomFilter<i32> = ::serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.counters, cbf.counters);
+        assert!(decoded.contains(&100));
+
+        let k = format!("\"k\":{}", cbf.k);
+        for &(from, to) in &[
+            (k.as_str(), "\"k\":0"),
+            (k.as_str(), "\"k\":1000000"),
+            ("\"width\":4", "\"width\":5"),
+        ] {
+            let corrupt = json.replacen(from, to, 1);
+            assert_ne!(corrupt, json);
+            assert!(::serde_json::from_str::<CountingBloomFilter<i32>>(&corrupt).is_err());
+        }
+    }
+}
@@ -0,0 +1,723 @@
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::mem;
+
+use codec;
+use error::Error;
+use hash::{fingerprint, splitmix64, SipBuildHasher};
+use packed::PackedVec;
+use sketch::{Estimator, Mergeable, Sketch};
+
+/// A cuckoo filter is a probabilistic set which stores a small fingerprint of each element in one
+/// of two candidate buckets, as described in [Cuckoo Filter: Practically Better Than
+/// Bloom](https://www.cs.cmu.edu/~dga/papers/cuckoo-conext2014.pdf) by Fan et al.
+///
+/// For false positive probabilities below about 3%, a cuckoo filter uses less space than a
+/// `BloomFilter`, and unlike a `BloomFilter` it supports removing elements. When both of an
+/// element's buckets are full, insertion evicts fingerprints to their alternate buckets; if that
+/// fails to find room, the filter is full and insertion returns `Error::Full`.
+///
+/// Fingerprints are stored as a multiset: inserting an element twice stores two fingerprints, and
+/// it must be removed twice. Only elements which have been inserted should be removed, since
+/// removing any other element may remove the fingerprint of an element which collides with it.
+///
+/// ```
+/// use sketchy::CuckooFilter;
+///
+/// // Create a filter which can handle 100K elements with a 0.1% maximum
+/// // probability of a false positive.
+/// let mut filter = CuckooFilter::new(100_000, 0.001);
+///
+/// filter.insert("one");
+/// filter.insert("two");
+/// assert!(filter.contains(&"one"));
+/// assert_eq!(filter.len(), 2);
+///
+/// assert!(filter.remove(&"one"));
+/// assert!(!filter.contains(&"one"));
+/// ```
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        bound(
+            serialize = "S: ::serde::Serialize",
+            deserialize = "S: ::serde::Deserialize<'de>"
+        ),
+        try_from = "CuckooFilterData<S>"
+    )
+)]
+pub struct CuckooFilter<E, S = SipBuildHasher> {
+    bucket_size: usize,
+    len: usize,
+    slots: PackedVec,
+    hasher: S,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    marker: PhantomData<E>,
+}
+
+/// The default number of fingerprints per bucket.
+const DEFAULT_BUCKET_SIZE: usize = 4;
+
+/// The maximum number of evictions attempted before an insertion fails.
+const MAX_KICKS: usize = 500;
+
+/// The largest number of buckets, which are indexed with 32 bits of the hash.
+const MAX_BUCKETS: u64 = 1 << 32;
+
+impl<E: Hash> CuckooFilter<E> {
+    /// Creates a new `CuckooFilter` with four fingerprints per bucket, tuned for a population of
+    /// `n` elements with the given upper bound of the probability of false positives.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the probability isn't in the range `(0, 1)` or the filter would be too large.
+    pub fn new(n: usize, max_false_pos_prob: f64) -> CuckooFilter<E> {
+        match CuckooFilter::try_new(n, max_false_pos_prob) {
+            Ok(cf) => cf,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Creates a new `CuckooFilter` with four fingerprints per bucket, tuned for a population of
+    /// `n` elements with the given upper bound of the probability of false positives, or returns
+    /// an error if the probability isn't in the range `(0, 1)` or the filter would be too large.
+    pub fn try_new(n: usize, max_false_pos_prob: f64) -> Result<CuckooFilter<E>, Error> {
+        let bits = fingerprint_bits(max_false_pos_prob, DEFAULT_BUCKET_SIZE)?;
+        CuckooFilter::with_fingerprint_size(n, bits, DEFAULT_BUCKET_SIZE)
+    }
+
+    /// Creates a new `CuckooFilter` with room for at least `n` elements, which stores fingerprints
+    /// of the given size in bits in buckets of the given size. Returns an error if the fingerprint
+    /// size isn't in the range `[2, 32]`, the bucket size isn't 1, 2, 4, or 8, or the filter would
+    /// be too large.
+    ///
+    /// A filter's probability of false positives is about `2 * bucket_size / 2^fingerprint_bits`.
+    pub fn with_fingerprint_size(
+        n: usize,
+        fingerprint_bits: usize,
+        bucket_size: usize,
+    ) -> Result<CuckooFilter<E>, Error> {
+        CuckooFilter::with_fingerprint_size_and_hasher(
+            n,
+            fingerprint_bits,
+            bucket_size,
+            SipBuildHasher::default(),
+        )
+    }
+
+    /// Returns the filter encoded in a compact, versioned binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = codec::header(codec::CUCKOO_FILTER, codec::HASH_SIPHASH24);
+        self.hasher.encode(&mut buf);
+        buf.push(self.slots.width() as u8);
+        buf.push(self.bucket_size as u8);
+        codec::put_varint(&mut buf, self.num_buckets() as u64);
+        codec::put_varint(&mut buf, self.len as u64);
+        for &w in self.slots.words() {
+            codec::put_u64(&mut buf, w);
+        }
+        codec::finish(buf)
+    }
+
+    /// Decodes a filter from the format produced by `to_bytes`, returning an error if the data is
+    /// corrupt or isn't an encoded `CuckooFilter`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<CuckooFilter<E>, Error> {
+        let mut input = codec::open(bytes, codec::CUCKOO_FILTER, codec::HASH_SIPHASH24)?;
+        let hasher = SipBuildHasher::decode(&mut input)?;
+        let width = codec::get_u8(&mut input)? as usize;
+        let bucket_size = codec::get_u8(&mut input)? as usize;
+        let buckets = codec::get_varint(&mut input)? as usize;
+        let len = codec::get_varint(&mut input)? as usize;
+        check_layout(width, bucket_size, buckets)?;
+
+        // Each word takes eight bytes.
+        let slots = buckets * bucket_size;
+        if (slots * width).div_ceil(64) > input.len() / mem::size_of::<u64>() {
+            return Err(Error::Truncated);
+        }
+
+        let mut slots = PackedVec::new(width, slots);
+        for w in slots.words_mut() {
+            *w = codec::get_u64(&mut input)?;
+        }
+        codec::close(input)?;
+        check_len(&slots, len)?;
+
+        Ok(CuckooFilter::<E> {
+            bucket_size,
+            len,
+            slots,
+            hasher,
+            marker: PhantomData,
+        })
+    }
+}
+
+impl<E: Hash, S: BuildHasher> CuckooFilter<E, S> {
+    /// Creates a new `CuckooFilter` with room for at least `n` elements, which stores fingerprints
+    /// of the given size in bits in buckets of the given size and hashes elements with the given
+    /// hasher. Returns an error if the fingerprint size isn't in the range `[2, 32]`, the bucket
+    /// size isn't 1, 2, 4, or 8, or the filter would be too large.
+    pub fn with_fingerprint_size_and_hasher(
+        n: usize,
+        fingerprint_bits: usize,
+        bucket_size: usize,
+        hasher: S,
+    ) -> Result<CuckooFilter<E, S>, Error> {
+        if !valid_fingerprint_bits(fingerprint_bits) {
+            return Err(Error::InvalidParameter(
+                "fingerprint size must be in [2, 32]",
+            ));
+        }
+
+        if !valid_bucket_size(bucket_size) {
+            return Err(Error::InvalidParameter("bucket size must be 1, 2, 4, or 8"));
+        }
+
+        // Buckets can't be filled completely before insertions start failing, and the larger
+        // they are, the fuller they can get.
+        let load = match bucket_size {
+            1 => 0.5,
+            2 => 0.84,
+            4 => 0.95,
+            _ => 0.98,
+        };
+        let buckets = ((n as f64 / (bucket_size as f64 * load)).ceil() as usize)
+            .max(1)
+            .checked_next_power_of_two()
+            .filter(|&b| b as u64 <= MAX_BUCKETS)
+            .ok_or(Error::InvalidParameter("too many elements"))?;
+
+        Ok(CuckooFilter::<E, S> {
+            bucket_size,
+            len: 0,
+            slots: PackedVec::new(fingerprint_bits, buckets * bucket_size),
+            hasher,
+            marker: PhantomData,
+        })
+    }
+
+    /// Returns the hasher used to hash elements.
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    /// Returns the size of the fingerprints, in bits.
+    pub fn fingerprint_bits(&self) -> usize {
+        self.slots.width()
+    }
+
+    /// Returns the number of fingerprints per bucket.
+    pub fn bucket_size(&self) -> usize {
+        self.bucket_size
+    }
+
+    /// Returns the number of fingerprints the filter has room for.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Returns the number of elements in the filter.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the filter contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds a value to the set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the filter is full.
+    pub fn insert(&mut self, e: E) {
+        if let Err(e) = self.try_insert(e) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Adds a value to the set, or returns `Error::Full` and leaves the filter unmodified if no
+    /// room can be made for it.
+    pub fn try_insert(&mut self, e: E) -> Result<(), Error> {
+        let (i, fp) = self.locate(&e);
+        self.insert_fingerprint(i, fp)
+    }
+
+    /// Returns `true` if the set probably contains the given element.
+    pub fn contains(&self, e: &E) -> bool {
+        let (i, fp) = self.locate(e);
+        self.find(i, fp).is_some() || self.find(self.alt_index(i, fp), fp).is_some()
+    }
+
+    /// Removes one copy of the given element from the set, returning `true` if its fingerprint was
+    /// found. The element must have been inserted; removing other elements may cause false
+    /// negatives.
+    pub fn remove(&mut self, e: &E) -> bool {
+        let (i, fp) = self.locate(e);
+        match self
+            .find(i, fp)
+            .or_else(|| self.find(self.alt_index(i, fp), fp))
+        {
+            Some(slot) => {
+                self.slots.set(slot, 0);
+                self.len -= 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Merges the contents of the given `CuckooFilter` into `self` by inserting each of its
+    /// fingerprints, so that elements present in both filters must be removed twice.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the filters have different parameters or hashers, or if `self` becomes full.
+    pub fn merge(&mut self, other: &CuckooFilter<E, S>) {
+        if let Err(e) = self.try_merge(other) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Merges the contents of the given `CuckooFilter` into `self` by inserting each of its
+    /// fingerprints, so that elements present in both filters must be removed twice. Returns an
+    /// error and leaves `self` unmodified if the filters have different parameters or hashers, or
+    /// if `self` becomes full.
+    pub fn try_merge(&mut self, other: &CuckooFilter<E, S>) -> Result<(), Error> {
+        self.check_compatible(other)?;
+        if self.len + other.len > self.capacity() {
+            return Err(Error::Full);
+        }
+
+        let (slots, len) = (self.slots.clone(), self.len);
+        for (slot, fp) in other.slots.iter().enumerate().filter(|&(_, fp)| fp != 0) {
+            if let Err(e) = self.insert_fingerprint(slot / self.bucket_size, fp) {
+                self.slots = slots;
+                self.len = len;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Clears the filter, removing all elements from the set.
+    pub fn clear(&mut self) {
+        for w in self.slots.words_mut() {
+            *w = 0;
+        }
+        self.len = 0;
+    }
+
+    /// Returns the approximate amount of memory used by the filter, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>() + self.slots.memory_usage()
+    }
+
+    fn num_buckets(&self) -> usize {
+        self.slots.len() / self.bucket_size
+    }
+
+    /// Returns the element's primary bucket and its fingerprint, which is never zero, since zero
+    /// marks an empty slot.
+    #[inline]
+    fn locate(&self, e: &E) -> (usize, u64) {
+        let h = self.hasher.hash_one(e);
+        let i = (h as u32 as usize) & (self.num_buckets() - 1);
+        let fp = (h >> 32) % self.slots.max_value() + 1;
+        (i, fp)
+    }
+
+    /// Returns the other bucket of a fingerprint in the given bucket. Since the buckets are indexed
+    /// with an XOR, this is its own inverse.
+    #[inline]
+    fn alt_index(&self, i: usize, fp: u64) -> usize {
+        let h = fp.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32;
+        (i ^ h as usize) & (self.num_buckets() - 1)
+    }
+
+    /// Returns the slot in the given bucket which holds the fingerprint, if any.
+    #[inline]
+    fn find(&self, i: usize, fp: u64) -> Option<usize> {
+        let start = i * self.bucket_size;
+        (start..start + self.bucket_size).find(|&slot| self.slots.get(slot) == fp)
+    }
+
+    /// Stores the fingerprint in the given bucket or its alternate, evicting other fingerprints if
+    /// both are full. If that fails, the evictions are undone.
+    fn insert_fingerprint(&mut self, i: usize, fp: u64) -> Result<(), Error> {
+        let alt = self.alt_index(i, fp);
+        if let Some(slot) = self.find(i, 0).or_else(|| self.find(alt, 0)) {
+            self.slots.set(slot, fp);
+            self.len += 1;
+            return Ok(());
+        }
+
+        let mut evicted = Vec::with_capacity(MAX_KICKS);
+        let (mut i, mut fp) = if choice(i, fp, 0) & 1 == 0 {
+            (i, fp)
+        } else {
+            (alt, fp)
+        };
+        for kick in 1..=MAX_KICKS {
+            let slot = i * self.bucket_size + choice(i, fp, kick) as usize % self.bucket_size;
+            let victim = self.slots.get(slot);
+            self.slots.set(slot, fp);
+            evicted.push((slot, victim));
+
+            fp = victim;
+            i = self.alt_index(i, fp);
+            if let Some(slot) = self.find(i, 0) {
+                self.slots.set(slot, fp);
+                self.len += 1;
+                return Ok(());
+            }
+        }
+
+        for (slot, victim) in evicted.into_iter().rev() {
+            self.slots.set(slot, victim);
+        }
+        Err(Error::Full)
+    }
+}
+
+impl<E: Hash, S: BuildHasher> Sketch<E> for CuckooFilter<E, S> {
+    fn insert(&mut self, e: E) {
+        CuckooFilter::insert(self, e)
+    }
+
+    fn clear(&mut self) {
+        CuckooFilter::clear(self)
+    }
+
+    fn memory_usage(&self) -> usize {
+        CuckooFilter::memory_usage(self)
+    }
+}
+
+impl<E: Hash, S: BuildHasher> Mergeable for CuckooFilter<E, S> {
+    fn check_compatible(&self, other: &Self) -> Result<(), Error> {
+        if self.bucket_size != other.bucket_size
+            || self.slots.width() != other.slots.width()
+            || self.slots.len() != other.slots.len()
+        {
+            return Err(Error::Incompatible("different parameters"));
+        }
+
+        if fingerprint(&self.hasher) != fingerprint(&other.hasher) {
+            return Err(Error::HasherMismatch);
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: &Self) -> Result<(), Error> {
+        self.try_merge(other)
+    }
+}
+
+impl<E: Hash, S: BuildHasher> Estimator<E> for CuckooFilter<E, S> {
+    type Estimate = bool;
+
+    fn estimate(&self, e: &E) -> bool {
+        self.contains(e)
+    }
+}
+
+/// Returns the smallest fingerprint size for which a filter with the given bucket size has at most
+/// the given probability of false positives. A query compares against `2 * bucket_size`
+/// fingerprints, each of which matches with a probability of `1 / (2^bits - 1)`.
+fn fingerprint_bits(max_false_pos_prob: f64, bucket_size: usize) -> Result<usize, Error> {
+    if !(max_false_pos_prob > 0.0 && max_false_pos_prob < 1.0) {
+        return Err(Error::InvalidErrorRate(max_false_pos_prob));
+    }
+
+    (2..=32)
+        .find(|&bits| {
+            let values = ((1u64 << bits) - 1) as f64;
+            1.0 - (1.0 - 1.0 / values).powi(2 * bucket_size as i32) <= max_false_pos_prob
+        })
+        .ok_or(Error::InvalidErrorRate(max_false_pos_prob))
+}
+
+fn valid_fingerprint_bits(bits: usize) -> bool {
+    (2..=32).contains(&bits)
+}
+
+fn valid_bucket_size(size: usize) -> bool {
+    size == 1 || size == 2 || size == 4 || size == 8
+}
+
+/// Returns a pseudorandom choice for the given eviction from a bucket, derived from the bucket, the
+/// fingerprint being placed, and the number of evictions so far, so that the same insertions
+/// always produce the same table.
+fn choice(i: usize, fp: u64, kick: usize) -> u64 {
+    splitmix64(splitmix64((i as u64) << 32 | fp) ^ kick as u64)
+}
+
+/// Checks the layout of an encoded or deserialized filter, as the constructors would.
+fn check_layout(width: usize, bucket_size: usize, buckets: usize) -> Result<(), Error> {
+    if !valid_fingerprint_bits(width) || !valid_bucket_size(bucket_size) {
+        return Err(Error::InvalidData("invalid fingerprint or bucket size"));
+    }
+
+    if !buckets.is_power_of_two() || buckets as u64 > MAX_BUCKETS {
+        return Err(Error::InvalidData("invalid number of buckets"));
+    }
+    Ok(())
+}
+
+/// Checks that the length of an encoded or deserialized filter matches its fingerprints.
+fn check_len(slots: &PackedVec, len: usize) -> Result<(), Error> {
+    if slots.iter().filter(|&fp| fp != 0).count() != len {
+        return Err(Error::InvalidData("inconsistent length"));
+    }
+    Ok(())
+}
+
+/// The fields of a deserialized `CuckooFilter`, which are checked before use.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct CuckooFilterData<S> {
+    bucket_size: usize,
+    len: usize,
+    slots: PackedVec,
+    hasher: S,
+}
+
+#[cfg(feature = "serde")]
+impl<E, S> ::std::convert::TryFrom<CuckooFilterData<S>> for CuckooFilter<E, S> {
+    type Error = Error;
+
+    fn try_from(data: CuckooFilterData<S>) -> Result<CuckooFilter<E, S>, Error> {
+        let width = data.slots.width();
+        let slots = data.slots.len();
+        if data.bucket_size == 0 || !slots.is_multiple_of(data.bucket_size) {
+            return Err(Error::InvalidData("invalid fingerprint or bucket size"));
+        }
+        check_layout(width, data.bucket_size, slots / data.bucket_size)?;
+        check_len(&data.slots, data.len)?;
+
+        Ok(CuckooFilter {
+            bucket_size: data.bucket_size,
+            len: data.len,
+            slots: data.slots,
+            hasher: data.hasher,
+            marker: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bloomfilter::BloomFilter;
+
+    #[test]
+    fn insert_and_query() {
+        let mut cf = CuckooFilter::new(100, 0.01);
+        assert!(cf.is_empty());
+        cf.insert(100);
+        cf.insert(400);
+
+        assert_eq!(cf.len(), 2);
+        assert!(cf.contains(&100));
+        assert!(cf.contains(&400));
+    }
+
+    #[test]
+    fn remove() {
+        let mut cf = CuckooFilter::new(100, 0.01);
+        cf.insert(100);
+        cf.insert(100);
+        cf.insert(400);
+
+        assert!(cf.remove(&100));
+        assert!(cf.contains(&100));
+        assert!(cf.remove(&100));
+        assert!(!cf.contains(&100));
+        assert!(!cf.remove(&100));
+        assert!(cf.contains(&400));
+        assert_eq!(cf.len(), 1);
+    }
+
+    #[test]
+    fn sizing() {
+        assert_eq!(fingerprint_bits(0.03, 4), Ok(9));
+        assert_eq!(fingerprint_bits(0.001, 4), Ok(13));
+        assert_eq!(fingerprint_bits(0.5, 1), Ok(3));
+        assert!(CuckooFilter::<u64>::try_new(100, 0.0).is_err());
+        assert!(CuckooFilter::<u64>::try_new(100, 1.0).is_err());
+        assert!(CuckooFilter::<u64>::try_new(100, 1e-12).is_err());
+        assert!(CuckooFilter::<u64>::try_new(usize::MAX, 0.01).is_err());
+        assert!(CuckooFilter::<u64>::with_fingerprint_size(100, 1, 4).is_err());
+        assert!(CuckooFilter::<u64>::with_fingerprint_size(100, 8, 3).is_err());
+
+        let cf = CuckooFilter::<u64>::new(1000, 0.01);
+        assert_eq!(cf.bucket_size(), 4);
+        assert!(cf.capacity() as f64 * 0.95 >= 1000.0);
+
+        // A cuckoo filter is smaller than a Bloom filter at low false positive probabilities.
+        for &p in &[0.001, 1e-4, 1e-6] {
+            assert!(
+                CuckooFilter::<u64>::new(120_000, p).memory_usage()
+                    < BloomFilter::<u64>::new(120_000, p).memory_usage()
+            );
+        }
+    }
+
+    #[test]
+    fn false_positive_rate() {
+        let p = 0.01;
+        let n = 10_000;
+        let mut cf = CuckooFilter::new(n, p);
+        for i in 0..n as u64 {
+            cf.insert(i);
+        }
+        assert!((0..n as u64).all(|i| cf.contains(&i)));
+
+        let trials = 100_000;
+        let false_positives = (n as u64..(n + trials) as u64)
+            .filter(|i| cf.contains(i))
+            .count();
+        assert!((false_positives as f64 / trials as f64) < p);
+    }
+
+    #[test]
+    fn full() {
+        let mut cf = CuckooFilter::with_fingerprint_size(8, 16, 2).unwrap();
+        let mut inserted = Vec::new();
+        let mut i = 0u64;
+        let err = loop {
+            let words = cf.slots.words().to_vec();
+            match cf.try_insert(i) {
+                Ok(()) => inserted.push(i),
+                Err(e) => {
+                    assert_eq!(cf.slots.words(), &words[..]);
+                    break e;
+                }
+            }
+            i += 1;
+        };
+
+        assert_eq!(err, Error::Full);
+        assert_eq!(cf.len(), inserted.len());
+        assert!(inserted.iter().all(|i| cf.contains(i)));
+
+        // Evictions are deterministic, so the same insertions fill the filter at the same point.
+        let mut again = CuckooFilter::with_fingerprint_size(8, 16, 2).unwrap();
+        for &i in inserted.iter() {
+            again.insert(i);
+        }
+        assert_eq!(again.slots, cf.slots);
+        assert_eq!(again.try_insert(i), Err(Error::Full));
+    }
+
+    #[test]
+    fn merge() {
+        let mut cf1 = CuckooFilter::new(100, 0.01);
+        cf1.insert(100);
+
+        let mut cf2 = CuckooFilter::new(100, 0.01);
+        cf2.insert(100);
+        cf2.insert(400);
+
+        cf1.merge(&cf2);
+        assert_eq!(cf1.len(), 3);
+        assert!(cf1.contains(&400));
+        assert!(cf1.remove(&100));
+        assert!(cf1.contains(&100));
+
+        assert_eq!(
+            cf1.try_merge(&CuckooFilter::new(10_000, 0.01)),
+            Err(Error::Incompatible("different parameters"))
+        );
+        assert_eq!(
+            cf1.try_merge(
+                &CuckooFilter::with_fingerprint_size_and_hasher(
+                    100,
+                    cf1.fingerprint_bits(),
+                    4,
+                    SipBuildHasher::new(1, 2)
+                )
+                .unwrap()
+            ),
+            Err(Error::HasherMismatch)
+        );
+    }
+
+    #[test]
+    fn merge_full() {
+        let mut cf1 = CuckooFilter::with_fingerprint_size(4, 16, 2).unwrap();
+        let mut cf2 = CuckooFilter::with_fingerprint_size(4, 16, 2).unwrap();
+        for i in 0..5 {
+            cf1.insert(i);
+            cf2.insert(i + 100);
+        }
+
+        let words = cf1.slots.words().to_vec();
+        assert_eq!(cf1.try_merge(&cf2), Err(Error::Full));
+        assert_eq!(cf1.slots.words(), &words[..]);
+        assert_eq!(cf1.len(), 5);
+    }
+
+    #[test]
+    fn clear() {
+        let mut cf = CuckooFilter::new(100, 0.01);
+        cf.insert(100);
+        cf.clear();
+        assert!(!cf.contains(&100));
+        assert!(cf.is_empty());
+    }
+
+    #[test]
+    fn to_and_from_bytes() {
+        let mut cf = CuckooFilter::new(1000, 0.01);
+        cf.insert(100);
+        cf.insert(400);
+
+        let bytes = cf.to_bytes();
+        let decoded = CuckooFilter::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.slots, cf.slots);
+        assert_eq!(decoded.len(), 2);
+        assert!(decoded.contains(&100));
+        assert!(decoded.contains(&400));
+
+        assert_eq!(
+            CuckooFilter::<u64>::from_bytes(&BloomFilter::<u64>::new(10, 0.1).to_bytes()).err(),
+            Some(Error::UnexpectedType(codec::BLOOM_FILTER))
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let mut cf = CuckooFilter::new(100, 0.01);
+        cf.insert(100);
+
+        let json = ::serde_json::to_string(&cf).unwrap();
+        let decoded: CuckooFilter<i32> = ::serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.slots, cf.slots);
+        assert!(decoded.contains(&100));
+
+        for &(from, to) in &[
+            ("\"bucket_size\":4", "\"bucket_size\":0"),
+            ("\"bucket_size\":4", "\"bucket_size\":3"),
+            ("\"len\":1", "\"len\":2"),
+        ] {
+            let corrupt = json.replacen(from, to, 1);
+            assert_ne!(corrupt, json);
+            assert!(::serde_json::from_str::<CuckooFilter<i32>>(&corrupt).is_err());
+        }
+
+        // Three buckets of four fingerprints.
+        let slots = PackedVec::new(cf.fingerprint_bits(), 12);
+        let json = format!(
+            r#"{{"bucket_size":4,"len":0,"slots":{},"hasher":{{"k0":0,"k1":0}}}}"#,
+            ::serde_json::to_string(&slots).unwrap()
+        );
+        assert!(::serde_json::from_str::<CuckooFilter<i32>>(&json).is_err());
+    }
+}
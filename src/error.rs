@@ -0,0 +1,55 @@
+use std::error;
+use std::fmt;
+
+/// The error type for operations on sketches.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The precision is outside the supported range.
+    InvalidPrecision(usize),
+    /// The margin of error is nonsensical or outside the supported range.
+    InvalidErrorRate(f64),
+    /// A parameter of the sketch is nonsensical or outside the supported range.
+    InvalidParameter(&'static str),
+    /// The sketches hash their elements differently, so they can't be combined.
+    HasherMismatch,
+    /// The sketches have different parameters, so they can't be combined.
+    Incompatible(&'static str),
+    /// The sketch has no room for another element.
+    Full,
+    /// The encoded sketch ended unexpectedly.
+    Truncated,
+    /// The data doesn't start with the magic bytes of an encoded sketch.
+    InvalidMagic,
+    /// The checksum of the encoded sketch doesn't match its contents.
+    ChecksumMismatch,
+    /// The encoded sketch has a different type tag than expected.
+    UnexpectedType(u8),
+    /// The encoded sketch uses an unsupported version of the format.
+    UnsupportedVersion(u8),
+    /// The encoded sketch uses a different hash scheme than expected.
+    UnsupportedHash(u8),
+    /// The encoded sketch is malformed.
+    InvalidData(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidPrecision(p) => write!(f, "unsupported precision: {}", p),
+            Error::InvalidErrorRate(e) => write!(f, "unsupported error rate: {}", e),
+            Error::InvalidParameter(s) => write!(f, "invalid parameter: {}", s),
+            Error::HasherMismatch => write!(f, "mismatched hashers"),
+            Error::Incompatible(s) => write!(f, "incompatible sketches: {}", s),
+            Error::Full => write!(f, "sketch is full"),
+            Error::Truncated => write!(f, "truncated sketch"),
+            Error::InvalidMagic => write!(f, "not an encoded sketch"),
+            Error::ChecksumMismatch => write!(f, "checksum mismatch"),
+            Error::UnexpectedType(t) => write!(f, "unexpected sketch type: {}", t),
+            Error::UnsupportedVersion(v) => write!(f, "unsupported format version: {}", v),
+            Error::UnsupportedHash(h) => write!(f, "unsupported hash scheme: {}", h),
+            Error::InvalidData(s) => write!(f, "invalid sketch: {}", s),
+        }
+    }
+}
+
+impl error::Error for Error {}
@@ -1,16 +1,17 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use std::cmp::min;
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::iter::Iterator;
 
+use codec;
+use error::Error;
+
 /// Returns an iterator of indexes for the given element with a maximum size. This uses [double
 /// hashing](https://www.eecs.harvard.edu/~michaelm/postscripts/tr-02-05.pdf), allowing for multiple indexes
-/// to be created from only two full runs through SipHash2-4.
-pub fn indexes<E: Hash>(e: &E, max: usize) -> Index {
-    let mut h = DefaultHasher::new();
-    e.hash(&mut h);
-    let hash1 = h.finish();
+/// to be created from only two full runs through the hash function.
+pub fn indexes<E: Hash, S: BuildHasher>(e: &E, max: usize, hasher: &S) -> Index {
+    let hash1 = hasher.hash_one(e);
 
-    h = DefaultHasher::new();
+    let mut h = hasher.build_hasher();
     h.write_u64(hash1);
     e.hash(&mut h);
     let hash2 = h.finish();
@@ -23,6 +24,35 @@ pub fn indexes<E: Hash>(e: &E, max: usize) -> Index {
     }
 }
 
+/// Returns a fingerprint of the given hasher, for checking whether two sketches hash their elements
+/// the same way without requiring hashers to implement `PartialEq`.
+pub fn fingerprint<S: BuildHasher>(hasher: &S) -> u64 {
+    hasher.hash_one(FINGERPRINT_PROBE)
+}
+
+/// The arbitrary value hashed to fingerprint hashers.
+const FINGERPRINT_PROBE: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Mixes a hash with a seed using MurmurHash3's finalizer, so that static filters can retry
+/// construction with different slots without rehashing their keys.
+#[inline]
+pub fn mix(h: u64, seed: u64) -> u64 {
+    let mut h = h.wrapping_add(seed);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
+
+/// Returns the next seed in a deterministic sequence, so that static filters are reproducible.
+pub fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
 pub struct Index {
     h1: u64,
     h2: u64,
@@ -40,14 +70,265 @@ impl Iterator for Index {
     }
 }
 
+/// A `BuildHasher` which creates [SipHash-2-4](https://www.aumasson.jp/siphash/siphash.pdf) hashers
+/// with an explicit 128-bit key.
+///
+/// Unlike the standard library's `DefaultHasher`, whose algorithm may change between Rust
+/// releases, SipHash-2-4 is fully specified, so the same key and input bytes always produce the
+/// same hash. The default key is all zeroes.
+///
+/// The bytes hashed for an element come from its `Hash` implementation, though, and the standard
+/// library doesn't guarantee that those are stable across Rust releases or platforms: e.g. `str`
+/// currently writes its bytes followed by `0xff`, and integers are written in native byte order.
+/// Elements hash identically across processes only if their `Hash` implementations write the same
+/// bytes, which is guaranteed only for types with hand-written implementations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SipBuildHasher {
+    k0: u64,
+    k1: u64,
+}
+
+impl SipBuildHasher {
+    /// Returns a new `SipBuildHasher` with the given key.
+    pub fn new(k0: u64, k1: u64) -> SipBuildHasher {
+        SipBuildHasher { k0, k1 }
+    }
+
+    /// Returns the key of the hasher.
+    pub fn keys(&self) -> (u64, u64) {
+        (self.k0, self.k1)
+    }
+
+    /// Appends the key to the buffer.
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        codec::put_u64(buf, self.k0);
+        codec::put_u64(buf, self.k1);
+    }
+
+    /// Decodes a key from the front of the input.
+    pub(crate) fn decode(input: &mut &[u8]) -> Result<SipBuildHasher, Error> {
+        let k0 = codec::get_u64(input)?;
+        let k1 = codec::get_u64(input)?;
+        Ok(SipBuildHasher::new(k0, k1))
+    }
+}
+
+impl BuildHasher for SipBuildHasher {
+    type Hasher = SipHasher24;
+
+    fn build_hasher(&self) -> SipHasher24 {
+        SipHasher24::new_with_keys(self.k0, self.k1)
+    }
+}
+
+/// An implementation of [SipHash-2-4](https://www.aumasson.jp/siphash/siphash.pdf).
+///
+/// Integers are always hashed as little-endian bytes, and `usize` and `isize` values are always
+/// hashed as 64-bit integers, so hashes are the same on every platform.
+#[derive(Clone, Debug)]
+pub struct SipHasher24 {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+    tail: u64,
+    ntail: usize,
+    length: usize,
+}
+
+impl SipHasher24 {
+    /// Returns a new `SipHasher24` with the given key.
+    pub fn new_with_keys(k0: u64, k1: u64) -> SipHasher24 {
+        SipHasher24 {
+            v0: k0 ^ 0x736f_6d65_7073_6575,
+            v1: k1 ^ 0x646f_7261_6e64_6f6d,
+            v2: k0 ^ 0x6c79_6765_6e65_7261,
+            v3: k1 ^ 0x7465_6462_7974_6573,
+            tail: 0,
+            ntail: 0,
+            length: 0,
+        }
+    }
+
+    #[inline]
+    fn compress(&mut self, m: u64) {
+        self.v3 ^= m;
+        self.round();
+        self.round();
+        self.v0 ^= m;
+    }
+
+    #[inline]
+    fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13) ^ self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16) ^ self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21) ^ self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17) ^ self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+}
+
+impl Hasher for SipHasher24 {
+    fn write(&mut self, mut msg: &[u8]) {
+        self.length = self.length.wrapping_add(msg.len());
+
+        // Fill up any partial word left over from the last write.
+        if self.ntail != 0 {
+            let needed = min(8 - self.ntail, msg.len());
+            for (i, &b) in msg[..needed].iter().enumerate() {
+                self.tail |= u64::from(b) << (8 * (self.ntail + i));
+            }
+            self.ntail += needed;
+            msg = &msg[needed..];
+
+            if self.ntail < 8 {
+                return;
+            }
+
+            let m = self.tail;
+            self.compress(m);
+            self.tail = 0;
+            self.ntail = 0;
+        }
+
+        let mut words = msg.chunks_exact(8);
+        for word in &mut words {
+            let mut b = [0; 8];
+            b.copy_from_slice(word);
+            self.compress(u64::from_le_bytes(b));
+        }
+
+        for (i, &b) in words.remainder().iter().enumerate() {
+            self.tail |= u64::from(b) << (8 * i);
+        }
+        self.ntail = words.remainder().len();
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16);
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32);
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        let mut state = self.clone();
+        let b = ((self.length as u64 & 0xff) << 56) | self.tail;
+        state.compress(b);
+        state.v2 ^= 0xff;
+        state.round();
+        state.round();
+        state.round();
+        state.round();
+        state.v0 ^ state.v1 ^ state.v2 ^ state.v3
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
     #[test]
     fn double_hashing() {
-        let v: Vec<usize> = indexes(&"whee", 100).take(10).collect();
+        let v: Vec<usize> = indexes(&"whee", 100, &SipBuildHasher::default())
+            .take(10)
+            .collect();
+
+        assert_eq!(v, vec![10, 78, 46, 14, 82, 66, 34, 2, 70, 38]);
+    }
+
+    #[test]
+    fn fingerprints() {
+        assert_eq!(
+            fingerprint(&SipBuildHasher::new(1, 2)),
+            fingerprint(&SipBuildHasher::new(1, 2))
+        );
+        assert_ne!(
+            fingerprint(&SipBuildHasher::new(1, 2)),
+            fingerprint(&SipBuildHasher::new(2, 1))
+        );
+    }
 
-        assert_eq!(v, vec![3, 67, 15, 79, 43, 7, 71, 19, 83, 47]);
+    #[test]
+    fn siphash_reference_vectors() {
+        // The first and last of the 64 test vectors from the SipHash paper.
+        let msg: Vec<u8> = (0..64).collect();
+        for &(len, expected) in &[
+            (0, 0x726f_db47_dd0e_0e31),
+            (1, 0x74f8_39c5_93dc_67fd),
+            (63, 0x958a_324c_eb06_4572),
+        ] {
+            let mut h = SipHasher24::new_with_keys(0x0706_0504_0302_0100, 0x0f0e_0d0c_0b0a_0908);
+            h.write(&msg[..len]);
+            assert_eq!(h.finish(), expected, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn split_writes() {
+        let msg: Vec<u8> = (0..64).collect();
+        let hasher = SipBuildHasher::new(1, 2);
+
+        let mut h = hasher.build_hasher();
+        h.write(&msg);
+        let expected = h.finish();
+
+        for &split in &[1, 3, 7, 8, 9, 30, 63] {
+            let mut h = hasher.build_hasher();
+            h.write(&msg[..split]);
+            h.write(&msg[split..]);
+            assert_eq!(h.finish(), expected, "split {}", split);
+        }
+    }
+
+    #[test]
+    fn stable_hashes() {
+        let hasher = SipBuildHasher::new(1, 2);
+        assert_eq!(hasher.hash_one("whee"), 0x8fa5_b407_7002_d44d);
+        assert_eq!(hasher.hash_one(12345u64), 0x9f7e_fe58_9aca_26e2);
+        assert_eq!(hasher.hash_one((1usize, -1i32)), 0xad34_b368_c906_26a6);
+        assert_ne!(
+            hasher.hash_one("whee"),
+            SipBuildHasher::default().hash_one("whee")
+        );
     }
 }
@@ -0,0 +1,372 @@
+use std::cmp::Ordering;
+use std::f64;
+
+/// An estimated quantity, along with the standard error of the estimate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Estimate {
+    /// The estimated value.
+    pub value: f64,
+    /// The standard error of the estimate.
+    pub std_error: f64,
+}
+
+/// Joint estimates of the sizes of the intersection, union, and differences of two multisets `A`
+/// and `B`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct JointEstimate {
+    /// The estimated size of `A \ B`.
+    pub only_a: Estimate,
+    /// The estimated size of `B \ A`.
+    pub only_b: Estimate,
+    /// The estimated size of `A ∩ B`.
+    pub intersection: Estimate,
+    /// The estimated size of `A ∪ B`.
+    pub union: Estimate,
+    /// The estimated Jaccard similarity of `A` and `B`, `|A ∩ B| / |A ∪ B|`.
+    pub jaccard: Estimate,
+}
+
+/// Returns the standard error of a cardinality estimate of `n` at precision `p`.
+pub fn std_error(p: usize, n: f64) -> f64 {
+    1.04 / f64::from(1 << p).sqrt() * n
+}
+
+/// Estimates the intersection and Jaccard similarity of two multisets from the estimated
+/// cardinalities of each and of their union, using the inclusion–exclusion principle.
+pub fn inclusion_exclusion(p: usize, a: f64, b: f64, u: f64) -> (Estimate, Estimate) {
+    let (ea, eb, eu) = (std_error(p, a), std_error(p, b), std_error(p, u));
+    let i = (a + b - u).max(0.0).min(a.min(b));
+    let ei = (ea * ea + eb * eb + eu * eu).sqrt();
+
+    let intersection = Estimate {
+        value: i,
+        std_error: ei,
+    };
+    let jaccard = if u > 0.0 {
+        Estimate {
+            value: i / u,
+            std_error: ((ei / u).powi(2) + (i * eu / (u * u)).powi(2)).sqrt(),
+        }
+    } else {
+        Estimate {
+            value: 0.0,
+            std_error: 0.0,
+        }
+    };
+    (intersection, jaccard)
+}
+
+/// Estimates the sizes of `A \ B`, `B \ A`, and `A ∩ B` from the registers of two sketches at
+/// precision `p`, using Ertl's joint maximum likelihood estimator as described in [New
+/// cardinality estimation algorithms for HyperLogLog sketches](https://arxiv.org/abs/1702.01284).
+///
+/// `initial` is an initial guess for the three sizes, and `fallback` is the standard error of the
+/// intersection to report if the expected Fisher information can't be inverted.
+pub fn maximum_likelihood(
+    p: usize,
+    a: &[u64],
+    b: &[u64],
+    initial: [f64; 3],
+    fallback: f64,
+) -> JointEstimate {
+    let likelihood = Likelihood::new(p, a, b);
+
+    // Maximize the log-likelihood over the logarithms of the rates, which keeps them positive.
+    let start = [
+        initial[0].max(1.0).ln(),
+        initial[1].max(1.0).ln(),
+        initial[2].max(1.0).ln(),
+    ];
+    let mut theta = nelder_mead(|t| -likelihood.log(exp(t)), start);
+    theta = nelder_mead(|t| -likelihood.log(exp(t)), theta);
+    let lambda = exp(theta);
+
+    let cov = likelihood.covariance(lambda);
+    let estimate = |value: f64, variance: Option<f64>, fallback: f64| Estimate {
+        value,
+        std_error: variance
+            .filter(|v| v.is_finite() && *v >= 0.0)
+            .map_or(fallback, f64::sqrt),
+    };
+
+    let u = lambda[0] + lambda[1] + lambda[2];
+    let jaccard_gradient = [
+        -lambda[2] / (u * u),
+        -lambda[2] / (u * u),
+        (u - lambda[2]) / (u * u),
+    ];
+    JointEstimate {
+        only_a: estimate(lambda[0], cov.map(|c| c[0][0]), fallback),
+        only_b: estimate(lambda[1], cov.map(|c| c[1][1]), fallback),
+        intersection: estimate(lambda[2], cov.map(|c| c[2][2]), fallback),
+        union: estimate(
+            u,
+            cov.map(|c| quadratic_form(&c, &[1.0; 3])),
+            std_error(p, u),
+        ),
+        jaccard: estimate(
+            lambda[2] / u,
+            cov.map(|c| quadratic_form(&c, &jaccard_gradient)),
+            fallback / u,
+        ),
+    }
+}
+
+/// The joint log-likelihood of the registers of two sketches, under the Poisson model where the
+/// elements of `A \ B`, `B \ A`, and `A ∩ B` arrive at separate rates.
+struct Likelihood {
+    p: usize,
+    q: usize,
+    // The number of register pairs with each pair of values, indexed by `k1 * (q + 2) + k2`.
+    counts: Vec<u32>,
+}
+
+impl Likelihood {
+    fn new(p: usize, a: &[u64], b: &[u64]) -> Likelihood {
+        let q = 64 - p;
+        let mut counts = vec![0; (q + 2) * (q + 2)];
+        for (&k1, &k2) in a.iter().zip(b.iter()) {
+            counts[k1 as usize * (q + 2) + k2 as usize] += 1;
+        }
+        Likelihood { p, q, counts }
+    }
+
+    /// Returns the log-likelihood of the registers given the rates of `A \ B`, `B \ A`, and
+    /// `A ∩ B`.
+    fn log(&self, lambda: [f64; 3]) -> f64 {
+        let width = self.q + 2;
+        let rates = self.rates(lambda);
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &n)| n > 0)
+            .map(|(i, &n)| f64::from(n) * self.log_pair(rates, i / width, i % width))
+            .fold(0.0, |sum, x| sum + x)
+    }
+
+    /// Returns the per-register rates for the given rates of `A \ B`, `B \ A`, and `A ∩ B`.
+    fn rates(&self, lambda: [f64; 3]) -> [f64; 3] {
+        let m = f64::from(1 << self.p);
+        [lambda[0] / m, lambda[1] / m, lambda[2] / m]
+    }
+
+    /// Returns the log of the probability of a pair of registers having the values `k1` and `k2`,
+    /// given the per-register rates of `A \ B`, `B \ A`, and `A ∩ B`.
+    fn log_pair(&self, rates: [f64; 3], k1: usize, k2: usize) -> f64 {
+        let (a, b, x) = (rates[0], rates[1], rates[2]);
+        if k1 < k2 {
+            // The registers differ, so the larger value came from an element of only one set.
+            self.log_pmf(b, k2) + self.log_pmf(a + x, k1)
+        } else if k1 > k2 {
+            self.log_pmf(a, k1) + self.log_pmf(b + x, k2)
+        } else {
+            // The registers are equal, either because an element of the intersection set both or
+            // because elements of both differences did.
+            let k = k1;
+            let lower = if k == 0 {
+                f64::NEG_INFINITY
+            } else {
+                self.log_cdf(x, k - 1)
+            };
+            log_add(
+                self.log_pmf(x, k) + self.log_cdf(a, k) + self.log_cdf(b, k),
+                lower + self.log_pmf(a, k) + self.log_pmf(b, k),
+            )
+        }
+    }
+
+    /// Returns the probability that an element doesn't increase a register past `k`.
+    fn g(&self, k: usize) -> f64 {
+        if k > self.q {
+            0.0
+        } else {
+            (0.5f64).powi(k as i32)
+        }
+    }
+
+    /// Returns the log of the probability a register with the given rate is at most `k`.
+    fn log_cdf(&self, rate: f64, k: usize) -> f64 {
+        -rate * self.g(k)
+    }
+
+    /// Returns the log of the probability a register with the given rate is exactly `k`.
+    fn log_pmf(&self, rate: f64, k: usize) -> f64 {
+        if k == 0 {
+            -rate
+        } else {
+            let delta = self.g(k - 1) - self.g(k);
+            -rate * self.g(k) + (-(-rate * delta).exp_m1()).ln()
+        }
+    }
+
+    /// Returns the covariance matrix of the rates, estimated as the inverse of the expected Fisher
+    /// information, or `None` if it can't be inverted.
+    fn covariance(&self, lambda: [f64; 3]) -> Option<[[f64; 3]; 3]> {
+        let m = f64::from(1 << self.p);
+
+        // Step away from the boundary so that central differences stay in the domain.
+        let mut h = [0.0; 3];
+        let mut at = [0.0; 3];
+        for i in 0..3 {
+            h[i] = 1e-4 * lambda[i].max(1.0);
+            at[i] = lambda[i].max(h[i] * 2.0);
+        }
+
+        let width = self.q + 2;
+        let mut info = [[0.0; 3]; 3];
+        for k1 in 0..width {
+            for k2 in 0..width {
+                let log_p = |i: usize, d: f64| {
+                    let mut l = at;
+                    l[i] += d;
+                    self.log_pair(self.rates(l), k1, k2)
+                };
+
+                let p = log_p(0, 0.0).exp();
+                if p == 0.0 {
+                    continue;
+                }
+
+                let mut score = [0.0; 3];
+                for i in 0..3 {
+                    score[i] = (log_p(i, h[i]) - log_p(i, -h[i])) / (2.0 * h[i]);
+                }
+                if score.iter().any(|s| !s.is_finite()) {
+                    continue;
+                }
+
+                for i in 0..3 {
+                    for j in 0..3 {
+                        info[i][j] += m * p * score[i] * score[j];
+                    }
+                }
+            }
+        }
+
+        invert(&info)
+    }
+}
+
+/// Minimizes `f` using the Nelder–Mead simplex method, starting at the given point.
+fn nelder_mead<F: Fn([f64; 3]) -> f64>(f: F, start: [f64; 3]) -> [f64; 3] {
+    let mut simplex: Vec<([f64; 3], f64)> = (0..4)
+        .map(|i| {
+            let mut x = start;
+            if i > 0 {
+                x[i - 1] += 0.5;
+            }
+            (x, f(x))
+        })
+        .collect();
+
+    for _ in 0..MAX_ITERATIONS {
+        simplex.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+        let (best, worst) = (simplex[0].1, simplex[3].1);
+        if (worst - best).abs() <= 1e-12 * (1.0 + best.abs()) {
+            break;
+        }
+
+        let mut centroid = [0.0; 3];
+        for &(x, _) in simplex.iter().take(3) {
+            for i in 0..3 {
+                centroid[i] += x[i] / 3.0;
+            }
+        }
+        let along = |t: f64| {
+            let mut x = [0.0; 3];
+            for i in 0..3 {
+                x[i] = centroid[i] + t * (simplex[3].0[i] - centroid[i]);
+            }
+            x
+        };
+
+        let reflected = along(-1.0);
+        let fr = f(reflected);
+        if fr < simplex[0].1 {
+            let expanded = along(-2.0);
+            let fe = f(expanded);
+            simplex[3] = if fe < fr {
+                (expanded, fe)
+            } else {
+                (reflected, fr)
+            };
+        } else if fr < simplex[2].1 {
+            simplex[3] = (reflected, fr);
+        } else {
+            let contracted = if fr < simplex[3].1 {
+                along(-0.5)
+            } else {
+                along(0.5)
+            };
+            let fc = f(contracted);
+            if fc < fr.min(simplex[3].1) {
+                simplex[3] = (contracted, fc);
+            } else {
+                let best = simplex[0].0;
+                for v in simplex.iter_mut().skip(1) {
+                    for (x, &b) in v.0.iter_mut().zip(best.iter()) {
+                        *x = b + 0.5 * (*x - b);
+                    }
+                    v.1 = f(v.0);
+                }
+            }
+        }
+    }
+
+    simplex
+        .iter()
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+        .unwrap()
+        .0
+}
+
+const MAX_ITERATIONS: usize = 2000;
+
+fn exp(t: [f64; 3]) -> [f64; 3] {
+    [t[0].exp(), t[1].exp(), t[2].exp()]
+}
+
+/// Returns `log(e^a + e^b)`.
+fn log_add(a: f64, b: f64) -> f64 {
+    let (hi, lo) = if a > b { (a, b) } else { (b, a) };
+    if hi == f64::NEG_INFINITY {
+        hi
+    } else {
+        hi + (lo - hi).exp().ln_1p()
+    }
+}
+
+/// Returns `vᵀMv`.
+fn quadratic_form(m: &[[f64; 3]; 3], v: &[f64; 3]) -> f64 {
+    let mut sum = 0.0;
+    for i in 0..3 {
+        for j in 0..3 {
+            sum += v[i] * m[i][j] * v[j];
+        }
+    }
+    sum
+}
+
+/// Returns the inverse of a 3x3 matrix, or `None` if it's singular.
+fn invert(m: &[[f64; 3]; 3]) -> Option<[[f64; 3]; 3]> {
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+
+    let det = m[0][0] * cofactor(0, 0) + m[0][1] * cofactor(0, 1) + m[0][2] * cofactor(0, 2);
+    if det == 0.0 || !det.is_finite() {
+        return None;
+    }
+
+    let mut inv = [[0.0; 3]; 3];
+    for (i, row) in inv.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = cofactor(j, i) / det;
+        }
+    }
+    Some(inv)
+}
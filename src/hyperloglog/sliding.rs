@@ -0,0 +1,468 @@
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::mem;
+
+use super::{decode_method, encode_method, estimate, rho, EstimationMethod, MAX_P, MIN_P};
+use codec;
+use error::Error;
+use hash::SipBuildHasher;
+
+/// A `SlidingHyperLogLog` estimates the cardinality of the elements of a multiset which were
+/// inserted within a sliding window of time, as described in [Sliding HyperLogLog: Estimating
+/// cardinality in a data stream over a sliding window](https://hal.archives-ouvertes.fr/hal-00465313).
+///
+/// Instead of a single value, each register holds a list of future possible maxima (LFPM): the
+/// `(timestamp, rho)` pairs which are the maximum of the register for some window ending at the
+/// most recent insertion.
+///
+/// ```
+/// use sketchy::SlidingHyperLogLog;
+///
+/// // Track up to an hour of elements with a 5% margin of error.
+/// let mut hll = SlidingHyperLogLog::new(0.05, 3600);
+///
+/// for t in 0..3600 {
+///     hll.insert(t % 1000, t);
+/// }
+///
+/// // Estimate the number of distinct elements seen in the last 5 minutes.
+/// assert!(hll.cardinality_since(3300) > 270.0);
+/// assert!(hll.cardinality_since(3300) < 330.0);
+/// ```
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        bound(
+            serialize = "S: ::serde::Serialize",
+            deserialize = "S: ::serde::Deserialize<'de>"
+        ),
+        try_from = "SlidingHyperLogLogData<S>"
+    )
+)]
+pub struct SlidingHyperLogLog<E, S = SipBuildHasher> {
+    p: usize,
+    window: u64,
+    now: u64,
+    registers: Vec<Vec<(u64, u8)>>,
+    method: EstimationMethod,
+    hasher: S,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    marker: PhantomData<E>,
+}
+
+impl<E: Hash> SlidingHyperLogLog<E> {
+    /// Returns a new `SlidingHyperLogLog` with the given margin of error which can estimate
+    /// cardinalities over windows of up to `window` units of time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the margin of error isn't in the range `(0, 1)` or would require a precision
+    /// greater than 18.
+    pub fn new(error: f64, window: u64) -> SlidingHyperLogLog<E> {
+        match SlidingHyperLogLog::try_new(error, window) {
+            Ok(hll) => hll,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Returns a new `SlidingHyperLogLog` with the given margin of error which can estimate
+    /// cardinalities over windows of up to `window` units of time, or an error if the margin of
+    /// error isn't in the range `(0, 1)` or would require a precision greater than 18.
+    pub fn try_new(error: f64, window: u64) -> Result<SlidingHyperLogLog<E>, Error> {
+        if !(error > 0.0 && error < 1.0) {
+            return Err(Error::InvalidErrorRate(error));
+        }
+
+        let p = (1.04 / error).powi(2).log2().ceil() as usize;
+        if p > MAX_P {
+            return Err(Error::InvalidErrorRate(error));
+        }
+        SlidingHyperLogLog::with_precision(p.max(MIN_P), window)
+    }
+
+    /// Returns a new `SlidingHyperLogLog` with `2^p` registers which can estimate cardinalities
+    /// over windows of up to `window` units of time, or an error if `p` isn't in the range
+    /// `[4, 18]`.
+    pub fn with_precision(p: usize, window: u64) -> Result<SlidingHyperLogLog<E>, Error> {
+        SlidingHyperLogLog::with_precision_and_hasher(p, window, SipBuildHasher::default())
+    }
+
+    /// Returns the sketch encoded in a compact, versioned binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = codec::header(codec::SLIDING_HYPERLOGLOG, codec::HASH_SIPHASH24);
+        self.hasher.encode(&mut buf);
+        buf.push(self.p as u8);
+        buf.push(encode_method(self.method));
+        codec::put_varint(&mut buf, self.window);
+        codec::put_varint(&mut buf, self.now);
+        for lfpm in self.registers.iter() {
+            codec::put_varint(&mut buf, lfpm.len() as u64);
+            let mut prev = 0;
+            for &(t, r) in lfpm.iter() {
+                codec::put_varint(&mut buf, t - prev);
+                buf.push(r);
+                prev = t;
+            }
+        }
+        codec::finish(buf)
+    }
+
+    /// Decodes a sketch from the format produced by `to_bytes`, returning an error if the data is
+    /// corrupt or isn't an encoded `SlidingHyperLogLog`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SlidingHyperLogLog<E>, Error> {
+        let mut input = codec::open(bytes, codec::SLIDING_HYPERLOGLOG, codec::HASH_SIPHASH24)?;
+        let hasher = SipBuildHasher::decode(&mut input)?;
+        let p = codec::get_u8(&mut input)? as usize;
+        let method = decode_method(codec::get_u8(&mut input)?)?;
+        let window = codec::get_varint(&mut input)?;
+        let mut hll = SlidingHyperLogLog::with_precision_and_hasher(p, window, hasher)?;
+        hll.method = method;
+        hll.now = codec::get_varint(&mut input)?;
+
+        for lfpm in hll.registers.iter_mut() {
+            let len = codec::get_len(&mut input)?;
+            for _ in 0..len {
+                let delta = codec::get_varint(&mut input)?;
+                let r = codec::get_u8(&mut input)?;
+
+                let t = match lfpm.last() {
+                    Some(&(pt, _)) => pt.checked_add(delta),
+                    None => Some(delta),
+                };
+                match t {
+                    Some(t) if valid_entry(lfpm.last(), t, r, hll.now, p) => lfpm.push((t, r)),
+                    _ => return Err(Error::InvalidData("invalid register")),
+                }
+            }
+        }
+        codec::close(input)?;
+        Ok(hll)
+    }
+}
+
+impl<E: Hash, S: BuildHasher> SlidingHyperLogLog<E, S> {
+    /// Returns a new `SlidingHyperLogLog` with `2^p` registers which can estimate cardinalities
+    /// over windows of up to `window` units of time and hashes elements with the given hasher, or
+    /// an error if `p` isn't in the range `[4, 18]`.
+    pub fn with_precision_and_hasher(
+        p: usize,
+        window: u64,
+        hasher: S,
+    ) -> Result<SlidingHyperLogLog<E, S>, Error> {
+        if !(MIN_P..=MAX_P).contains(&p) {
+            return Err(Error::InvalidPrecision(p));
+        }
+
+        Ok(SlidingHyperLogLog::<E, S> {
+            p,
+            window,
+            now: 0,
+            registers: vec![Vec::new(); 1 << p],
+            method: EstimationMethod::default(),
+            hasher,
+            marker: PhantomData,
+        })
+    }
+
+    /// Returns the precision of the sketch, which uses `2^p` registers.
+    pub fn precision(&self) -> usize {
+        self.p
+    }
+
+    /// Returns the length of the longest window the sketch can estimate.
+    pub fn window(&self) -> u64 {
+        self.window
+    }
+
+    /// Returns the hasher used to hash elements.
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    /// Returns the method used to estimate the cardinality of the sketch.
+    pub fn estimation_method(&self) -> EstimationMethod {
+        self.method
+    }
+
+    /// Sets the method used to estimate the cardinality of the sketch.
+    pub fn set_estimation_method(&mut self, method: EstimationMethod) {
+        self.method = method;
+    }
+
+    /// Inserts an element of the multiset which arrived at the given time.
+    pub fn insert(&mut self, e: E, timestamp: u64) {
+        let x = self.hasher.hash_one(&e);
+        self.insert_hashed(x, timestamp);
+    }
+
+    /// Inserts the hash of an element of the multiset which arrived at the given time.
+    ///
+    /// Use this only when you have firm opinions about hash algorithms (i.e., SipHash2-4 is too
+    /// slow for you) or you're working with data which is already hashed. Otherwise, use `insert`.
+    pub fn insert_hashed(&mut self, x: u64, timestamp: u64) {
+        // j = <x_0x_1..x_{p-1}>
+        let j = (x & ((1 << self.p) - 1)) as usize;
+
+        // w = <x_{p}x_{p+1}..>
+        let r = rho(x >> self.p, 64 - self.p as isize) as u8;
+
+        self.now = self.now.max(timestamp);
+        let oldest = self.now.saturating_sub(self.window);
+        let lfpm = &mut self.registers[j];
+
+        // Drop expired pairs, and pairs which can no longer be a maximum because the new pair is
+        // both at least as recent and at least as large.
+        lfpm.retain(|&(t, v)| t >= oldest && !(t <= timestamp && v <= r));
+
+        // Keep the new pair only if no existing pair is both at least as recent and as large.
+        if timestamp >= oldest && !lfpm.iter().any(|&(t, v)| t >= timestamp && v >= r) {
+            let idx = lfpm
+                .iter()
+                .position(|&(t, _)| t > timestamp)
+                .unwrap_or(lfpm.len());
+            lfpm.insert(idx, (timestamp, r));
+        }
+    }
+
+    /// Returns an estimate of the cardinality of the elements inserted at or after the given
+    /// time. Elements older than the sketch's window before the most recent insertion are not
+    /// counted.
+    pub fn cardinality_since(&self, timestamp: u64) -> f64 {
+        let oldest = self.now.saturating_sub(self.window).max(timestamp);
+        let mut counts = vec![0; 66 - self.p];
+        for lfpm in self.registers.iter() {
+            // The pairs are ordered by time and decreasing value, so the first pair in the window
+            // is the maximum.
+            let r = lfpm
+                .iter()
+                .find(|&&(t, _)| t >= oldest)
+                .map_or(0, |&(_, r)| r);
+            counts[r as usize] += 1;
+        }
+        estimate(self.p, &counts, self.method)
+    }
+
+    /// Returns an estimate of the cardinality of the elements inserted within the sketch's entire
+    /// window.
+    pub fn cardinality(&self) -> f64 {
+        self.cardinality_since(0)
+    }
+
+    /// Returns the approximate amount of memory used by the sketch, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>()
+            + self
+                .registers
+                .iter()
+                .map(|r| {
+                    mem::size_of::<Vec<(u64, u8)>>() + r.capacity() * mem::size_of::<(u64, u8)>()
+                })
+                .sum::<usize>()
+    }
+}
+
+impl<E, S: Clone> Clone for SlidingHyperLogLog<E, S> {
+    fn clone(&self) -> SlidingHyperLogLog<E, S> {
+        SlidingHyperLogLog::<E, S> {
+            p: self.p,
+            window: self.window,
+            now: self.now,
+            registers: self.registers.clone(),
+            method: self.method,
+            hasher: self.hasher.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+/// Returns `true` if the entry `(t, r)` can follow `last` in a register of a sketch at precision
+/// `p` whose latest timestamp is `now`. Timestamps must be increasing and values decreasing.
+fn valid_entry(last: Option<&(u64, u8)>, t: u64, r: u8, now: u64, p: usize) -> bool {
+    let ordered = match last {
+        Some(&(pt, pr)) => t > pt && r < pr,
+        None => true,
+    };
+    ordered && t <= now && r > 0 && r as usize <= 65 - p
+}
+
+/// The fields of a deserialized `SlidingHyperLogLog`, which are checked before use.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct SlidingHyperLogLogData<S> {
+    p: usize,
+    window: u64,
+    now: u64,
+    registers: Vec<Vec<(u64, u8)>>,
+    method: EstimationMethod,
+    hasher: S,
+}
+
+#[cfg(feature = "serde")]
+impl<E, S> ::std::convert::TryFrom<SlidingHyperLogLogData<S>> for SlidingHyperLogLog<E, S> {
+    type Error = Error;
+
+    fn try_from(data: SlidingHyperLogLogData<S>) -> Result<SlidingHyperLogLog<E, S>, Error> {
+        if !(MIN_P..=MAX_P).contains(&data.p) || data.registers.len() != 1 << data.p {
+            return Err(Error::InvalidData("invalid precision"));
+        }
+
+        for lfpm in data.registers.iter() {
+            let mut last = None;
+            for e in lfpm.iter() {
+                if !valid_entry(last, e.0, e.1, data.now, data.p) {
+                    return Err(Error::InvalidData("invalid register"));
+                }
+                last = Some(e);
+            }
+        }
+
+        Ok(SlidingHyperLogLog::<E, S> {
+            p: data.p,
+            window: data.window,
+            now: data.now,
+            registers: data.registers,
+            method: data.method,
+            hasher: data.hasher,
+            marker: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::HyperLogLog;
+    use super::*;
+
+    #[test]
+    fn matches_hyperloglog() {
+        let mut sliding = SlidingHyperLogLog::with_precision(10, 1000).unwrap();
+        for t in 0..10_000 {
+            sliding.insert(t, t);
+        }
+
+        for &since in &[9000, 9500, 9900, 9990] {
+            let mut hll = HyperLogLog::<u64>::with_precision(10).unwrap();
+            hll.densify();
+            for t in since..10_000 {
+                hll.insert(t);
+            }
+
+            assert_eq!(sliding.cardinality_since(since), hll.cardinality());
+        }
+    }
+
+    #[test]
+    fn expiry() {
+        let mut sliding = SlidingHyperLogLog::with_precision(10, 100).unwrap();
+        for t in 0..10_000 {
+            sliding.insert(t, t);
+        }
+
+        let mut hll = HyperLogLog::<u64>::with_precision(10).unwrap();
+        hll.densify();
+        for t in 9899..10_000 {
+            hll.insert(t);
+        }
+
+        assert_eq!(sliding.cardinality(), hll.cardinality());
+        assert_eq!(sliding.cardinality_since(0), hll.cardinality());
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut a = SlidingHyperLogLog::with_precision(8, 1000).unwrap();
+        let mut b = SlidingHyperLogLog::with_precision(8, 1000).unwrap();
+        for t in 0..1000 {
+            a.insert(t, t);
+        }
+        for t in (0..1000).rev() {
+            b.insert(t, t);
+        }
+
+        assert_eq!(a.registers, b.registers);
+    }
+
+    #[test]
+    fn lfpm_is_ordered() {
+        let mut sliding = SlidingHyperLogLog::with_precision(4, 1_000_000).unwrap();
+        for t in 0..100_000 {
+            sliding.insert(t, t);
+        }
+
+        for lfpm in sliding.registers.iter() {
+            for w in lfpm.windows(2) {
+                assert!(w[0].0 < w[1].0);
+                assert!(w[0].1 > w[1].1);
+            }
+        }
+    }
+
+    #[test]
+    fn to_and_from_bytes() {
+        let mut sliding = SlidingHyperLogLog::with_precision(8, 1000).unwrap();
+        sliding.set_estimation_method(EstimationMethod::Improved);
+        for t in 0..5000 {
+            sliding.insert(t, t);
+        }
+
+        let bytes = sliding.to_bytes();
+        let decoded = SlidingHyperLogLog::<u64>::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.registers, sliding.registers);
+        assert_eq!(decoded.window, 1000);
+        assert_eq!(decoded.now, 4999);
+        assert_eq!(decoded.estimation_method(), EstimationMethod::Improved);
+        assert_eq!(
+            decoded.cardinality_since(4500),
+            sliding.cardinality_since(4500)
+        );
+
+        assert_eq!(
+            HyperLogLog::<u64>::from_bytes(&bytes).err(),
+            Some(Error::UnexpectedType(codec::SLIDING_HYPERLOGLOG))
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let mut sliding = SlidingHyperLogLog::with_precision(8, 1000).unwrap();
+        for t in 0..5000 {
+            sliding.insert(t, t);
+        }
+
+        let json = ::serde_json::to_string(&sliding).unwrap();
+        let decoded: SlidingHyperLogLog<u64> = ::serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.registers, sliding.registers);
+        assert_eq!(
+            decoded.cardinality_since(4500),
+            sliding.cardinality_since(4500)
+        );
+
+        for &(old, new) in &[
+            (r#""p":8"#, r#""p":2"#),
+            (r#""p":8"#, r#""p":9"#),
+            (r#""now":4999"#, r#""now":0"#),
+        ] {
+            let corrupt = json.replacen(old, new, 1);
+            assert!(::serde_json::from_str::<SlidingHyperLogLog<u64>>(&corrupt).is_err());
+        }
+
+        let mut unordered = sliding.clone();
+        unordered.registers[0] = vec![(10, 1), (20, 2)];
+        let json = ::serde_json::to_string(&unordered).unwrap();
+        assert!(::serde_json::from_str::<SlidingHyperLogLog<u64>>(&json).is_err());
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        assert_eq!(
+            SlidingHyperLogLog::<u64>::with_precision(19, 10).err(),
+            Some(Error::InvalidPrecision(19))
+        );
+        assert_eq!(
+            SlidingHyperLogLog::<u64>::try_new(0.0, 10).err(),
+            Some(Error::InvalidErrorRate(0.0))
+        );
+    }
+}
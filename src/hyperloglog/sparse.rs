@@ -0,0 +1,343 @@
+use std::cmp::max;
+use std::iter::from_fn;
+use std::mem;
+
+use super::rho;
+use codec;
+use error::Error;
+
+/// The precision used to index registers in the sparse representation.
+pub const SPARSE_P: usize = 25;
+
+const INDEX_MASK: u64 = (1 << SPARSE_P) - 1;
+const RHO_BITS: u32 = 6;
+const RHO_MASK: u32 = (1 << RHO_BITS) - 1;
+
+/// The sparse representation of a `HyperLogLog`, as described in [HyperLogLog in
+/// Practice](https://research.google.com/pubs/pub40671.html). Each non-zero register of a
+/// precision-25 sketch is stored as an `(index, rho)` pair packed into a `u32`. The pairs are kept
+/// sorted and delta-encoded as varints, with recent insertions held in an unsorted buffer until it
+/// fills up.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SparseRegisters {
+    list: Vec<u8>,
+    len: usize,
+    buffer: Vec<u32>,
+    buffer_size: usize,
+}
+
+impl SparseRegisters {
+    /// Returns an empty set of sparse registers which buffers up to `buffer_size` insertions.
+    pub fn new(buffer_size: usize) -> SparseRegisters {
+        SparseRegisters {
+            list: Vec::new(),
+            len: 0,
+            buffer: Vec::new(),
+            buffer_size,
+        }
+    }
+
+    /// Sets the number of insertions to buffer.
+    pub fn set_buffer_size(&mut self, buffer_size: usize) {
+        self.buffer_size = buffer_size;
+        if self.buffer.len() >= buffer_size {
+            self.flush();
+        }
+    }
+
+    /// Records the given hash. Returns `true` if the buffer was flushed into the sorted list.
+    pub fn insert_hashed(&mut self, x: u64) -> bool {
+        // idx = <x_0x_1..x_{24}>
+        let idx = (x & INDEX_MASK) as u32;
+
+        // w = <x_{25}x_{26}..>
+        let w = x >> SPARSE_P;
+
+        self.insert(idx, rho(w, 64 - SPARSE_P as isize) as u32)
+    }
+
+    /// Records the given register value. Returns `true` if the buffer was flushed into the sorted
+    /// list.
+    pub fn insert(&mut self, idx: u32, rho: u32) -> bool {
+        self.buffer.push((idx << RHO_BITS) | rho);
+        if self.buffer.len() >= self.buffer_size {
+            self.flush();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Merges the buffered insertions into the sorted list.
+    pub fn flush(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        let mut buffer = mem::take(&mut self.buffer);
+        buffer.sort_unstable();
+
+        let mut list = Vec::with_capacity(self.list.len() + buffer.len());
+        let mut len = 0;
+        let mut prev = 0;
+        for v in merge(decode(&self.list), buffer.into_iter()) {
+            encode(&mut list, v - prev);
+            prev = v;
+            len += 1;
+        }
+
+        self.list = list;
+        self.len = len;
+    }
+
+    /// Returns the size of the sorted list, in bytes.
+    pub fn size(&self) -> usize {
+        self.list.len()
+    }
+
+    /// Returns the amount of heap memory used, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.list.capacity() + self.buffer.capacity() * mem::size_of::<u32>()
+    }
+
+    /// Returns the number of non-zero registers.
+    pub fn count(&self) -> usize {
+        if self.buffer.is_empty() {
+            self.len
+        } else {
+            self.iter().count()
+        }
+    }
+
+    /// Returns an iterator of `(index, rho)` pairs for all non-zero registers, in index order.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        let mut buffer = self.buffer.clone();
+        buffer.sort_unstable();
+        merge(decode(&self.list), buffer.into_iter()).map(|v| (v >> RHO_BITS, v & RHO_MASK))
+    }
+
+    /// Appends the number of non-zero registers and their delta-encoded values to the buffer.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let mut buffer = self.buffer.clone();
+        buffer.sort_unstable();
+        let values: Vec<u32> = merge(decode(&self.list), buffer.into_iter()).collect();
+
+        codec::put_varint(buf, values.len() as u64);
+        let mut prev = 0;
+        for v in values {
+            codec::put_varint(buf, u64::from(v - prev));
+            prev = v;
+        }
+    }
+
+    /// Decodes registers encoded by `encode` from the front of the input.
+    pub fn decode(input: &mut &[u8], buffer_size: usize) -> Result<SparseRegisters, Error> {
+        let len = codec::get_len(input)?;
+        let mut list = Vec::new();
+        let mut prev = 0u64;
+        for i in 0..len {
+            let delta = codec::get_varint(input)?;
+            let v = prev.saturating_add(delta);
+            if !valid(v) || (i > 0 && v >> RHO_BITS <= prev >> RHO_BITS) {
+                return Err(Error::InvalidData("invalid sparse register"));
+            }
+
+            encode(&mut list, delta as u32);
+            prev = v;
+        }
+
+        Ok(SparseRegisters {
+            list,
+            len,
+            buffer: Vec::new(),
+            buffer_size,
+        })
+    }
+
+    /// Returns an error if the registers weren't built by `insert` or `decode`, e.g. if they were
+    /// deserialized from corrupt data.
+    #[cfg(feature = "serde")]
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = Error::InvalidData("invalid sparse register");
+
+        // Re-encode the list to make sure it's in canonical form.
+        let mut input = &self.list[..];
+        let mut list = Vec::with_capacity(self.list.len());
+        let mut len = 0;
+        let mut prev = 0u64;
+        while !input.is_empty() {
+            let delta = codec::get_varint(&mut input).map_err(|_| invalid.clone())?;
+            let v = prev.saturating_add(delta);
+            if !valid(v) || (len > 0 && v >> RHO_BITS <= prev >> RHO_BITS) {
+                return Err(invalid);
+            }
+            encode(&mut list, delta as u32);
+            len += 1;
+            prev = v;
+        }
+
+        if list != self.list || len != self.len || !self.buffer.iter().all(|&v| valid(u64::from(v)))
+        {
+            return Err(invalid);
+        }
+        Ok(())
+    }
+
+    /// Merges the registers of the given `SparseRegisters` into `self`.
+    pub fn merge(&mut self, other: &SparseRegisters) {
+        for (idx, rho) in other.iter() {
+            self.insert(idx, rho);
+        }
+        self.flush();
+    }
+}
+
+/// Converts a sparse register index and value to a register index and value at precision `p`.
+pub fn to_dense(idx: u32, rho: u32, p: usize) -> (usize, u64) {
+    let j = idx as usize & ((1 << p) - 1);
+    if rho <= (64 - SPARSE_P) as u32 {
+        // The leading bits of the hash are the same at both precisions.
+        (j, u64::from(rho))
+    } else {
+        // The hash had no set bits past the sparse index, so the rest of the index counts.
+        let hi = u64::from(idx >> p);
+        (j, 65 - p as u64 - u64::from(64 - hi.leading_zeros()))
+    }
+}
+
+/// Returns `true` if the given value is a valid encoded register.
+fn valid(v: u64) -> bool {
+    let (idx, rho) = (v >> RHO_BITS, v & u64::from(RHO_MASK));
+    v <= u64::from(u32::MAX) && idx <= INDEX_MASK && rho > 0 && rho <= (65 - SPARSE_P) as u64
+}
+
+/// Merges two sorted sequences of encoded registers, keeping only the maximum value for each index.
+fn merge<A, B>(a: A, b: B) -> impl Iterator<Item = u32>
+where
+    A: Iterator<Item = u32>,
+    B: Iterator<Item = u32>,
+{
+    let mut a = a.peekable();
+    let mut b = b.peekable();
+    let mut next = None;
+    from_fn(move || loop {
+        let v = match (a.peek(), b.peek()) {
+            (Some(&x), Some(&y)) if x <= y => a.next(),
+            (Some(_), Some(_)) => b.next(),
+            (Some(_), None) => a.next(),
+            (None, Some(_)) => b.next(),
+            (None, None) => return next.take(),
+        }
+        .unwrap();
+
+        match next {
+            Some(n) if n >> RHO_BITS == v >> RHO_BITS => next = Some(max(n, v)),
+            Some(n) => {
+                next = Some(v);
+                return Some(n);
+            }
+            None => next = Some(v),
+        }
+    })
+}
+
+/// Returns an iterator of the values in a delta-encoded list of varints.
+fn decode(list: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    let mut i = 0;
+    let mut prev = 0;
+    from_fn(move || {
+        if i >= list.len() {
+            return None;
+        }
+
+        let mut delta = 0;
+        let mut shift = 0;
+        loop {
+            let b = list[i];
+            i += 1;
+            delta |= u32::from(b & 0x7f) << shift;
+            shift += 7;
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+
+        prev += delta;
+        Some(prev)
+    })
+}
+
+/// Appends the given value as a varint.
+fn encode(list: &mut Vec<u8>, mut v: u32) {
+    while v >= 0x80 {
+        list.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    list.push(v as u8);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut sparse = SparseRegisters::new(4);
+        sparse.insert(10, 3);
+        sparse.insert(2, 1);
+        sparse.insert(10, 2);
+        sparse.insert(300_000, 40);
+        sparse.insert(2, 5);
+        sparse.insert(7, 1);
+
+        assert_eq!(
+            sparse.iter().collect::<Vec<_>>(),
+            vec![(2, 5), (7, 1), (10, 3), (300_000, 40)]
+        );
+        assert_eq!(sparse.count(), 4);
+
+        let mut buf = Vec::new();
+        sparse.encode(&mut buf);
+        let decoded = SparseRegisters::decode(&mut &buf[..], 4).unwrap();
+        assert_eq!(
+            decoded.iter().collect::<Vec<_>>(),
+            vec![(2, 5), (7, 1), (10, 3), (300_000, 40)]
+        );
+
+        sparse.flush();
+
+        assert_eq!(
+            sparse.iter().collect::<Vec<_>>(),
+            vec![(2, 5), (7, 1), (10, 3), (300_000, 40)]
+        );
+        assert_eq!(sparse.count(), 4);
+    }
+
+    #[test]
+    fn dense_conversion() {
+        for &x in &[
+            0u64,
+            1,
+            0xffff,
+            1 << 30,
+            1 << 24,
+            1 << 10,
+            u64::MAX,
+            0x1234_5678_9abc,
+        ] {
+            let mut sparse = SparseRegisters::new(1);
+            sparse.insert_hashed(x);
+            let (idx, rho) = sparse.iter().next().unwrap();
+
+            for p in 4..19 {
+                let j = x & ((1 << p) - 1);
+                let w = x >> p;
+                assert_eq!(
+                    to_dense(idx, rho, p),
+                    (j as usize, super::rho(w, 64 - p as isize))
+                );
+            }
+        }
+    }
+}
@@ -3,16 +3,47 @@
 
 extern crate bit_vec;
 extern crate rand;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 
+mod blockedbloom;
 mod bloomfilter;
+mod bloomparams;
+mod codec;
+mod countingbloom;
 mod countmin;
+mod cuckoo;
+mod error;
 mod hash;
 mod hyperloglog;
+mod packed;
+mod quotient;
 mod reservoir;
+mod ribbon;
+mod scalablebloom;
+mod sketch;
 mod topk;
+mod xorfilter;
 
+pub use blockedbloom::BlockedBloomFilter;
 pub use bloomfilter::BloomFilter;
+pub use bloomparams::BloomParams;
+pub use codec::Codec;
+pub use countingbloom::CountingBloomFilter;
 pub use countmin::CountMinSketch;
-pub use hyperloglog::HyperLogLog;
+pub use cuckoo::CuckooFilter;
+pub use error::Error;
+pub use hash::{SipBuildHasher, SipHasher24};
+pub use hyperloglog::{
+    Estimate, EstimationMethod, HyperLogLog, JointEstimate, SlidingHyperLogLog,
+};
+pub use quotient::QuotientFilter;
 pub use reservoir::ReservoirSample;
+pub use ribbon::{RibbonFilter, RibbonFilterBuilder};
+pub use scalablebloom::ScalableBloomFilter;
+pub use sketch::{Estimator, Mergeable, Sketch};
 pub use topk::TopK;
+pub use xorfilter::{BinaryFuseFilter16, BinaryFuseFilter8, XorFilter8};
@@ -0,0 +1,177 @@
+use std::mem;
+
+#[cfg(feature = "serde")]
+use error::Error;
+
+/// A fixed-length array of unsigned integers of a fixed bit width, packed contiguously into 64-bit
+/// words. Values may straddle word boundaries.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "PackedVecData")
+)]
+pub struct PackedVec {
+    width: usize,
+    len: usize,
+    words: Vec<u64>,
+}
+
+impl PackedVec {
+    /// Returns a new `PackedVec` of `len` zeroes, each `width` bits wide.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `width` isn't in the range `[1, 64]`.
+    pub fn new(width: usize, len: usize) -> PackedVec {
+        assert!((1..=64).contains(&width), "invalid width: {}", width);
+        PackedVec {
+            width,
+            len,
+            words: vec![0; (width * len).div_ceil(64)],
+        }
+    }
+
+    /// Returns the width of the values, in bits.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the number of values.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the largest value which can be stored.
+    pub fn max_value(&self) -> u64 {
+        u64::MAX >> (64 - self.width)
+    }
+
+    /// Returns the value at the given index.
+    #[inline]
+    pub fn get(&self, i: usize) -> u64 {
+        debug_assert!(i < self.len);
+        let bit = i * self.width;
+        let (w, off) = (bit / 64, bit % 64);
+        let mut v = self.words[w] >> off;
+        if off + self.width > 64 {
+            v |= self.words[w + 1] << (64 - off);
+        }
+        v & self.max_value()
+    }
+
+    /// Sets the value at the given index, truncating it to the width of the array.
+    #[inline]
+    pub fn set(&mut self, i: usize, v: u64) {
+        debug_assert!(i < self.len);
+        let mask = self.max_value();
+        let v = v & mask;
+        let bit = i * self.width;
+        let (w, off) = (bit / 64, bit % 64);
+        self.words[w] = (self.words[w] & !(mask << off)) | (v << off);
+        if off + self.width > 64 {
+            let shift = 64 - off;
+            self.words[w + 1] = (self.words[w + 1] & !(mask >> shift)) | (v >> shift);
+        }
+    }
+
+    /// Returns an iterator of the values.
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        (0..self.len).map(move |i| self.get(i))
+    }
+
+    /// Returns the words the values are packed into.
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// Returns a mutable slice of the words the values are packed into.
+    pub fn words_mut(&mut self) -> &mut [u64] {
+        &mut self.words
+    }
+
+    /// Returns the size of the packed values, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.words.capacity() * mem::size_of::<u64>()
+    }
+}
+
+/// The fields of a deserialized `PackedVec`, which are checked before use.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct PackedVecData {
+    width: usize,
+    len: usize,
+    words: Vec<u64>,
+}
+
+#[cfg(feature = "serde")]
+impl ::std::convert::TryFrom<PackedVecData> for PackedVec {
+    type Error = Error;
+
+    fn try_from(data: PackedVecData) -> Result<PackedVec, Error> {
+        let words = data
+            .width
+            .checked_mul(data.len)
+            .map(|bits| bits.div_ceil(64));
+        if !(1..=64).contains(&data.width) || words != Some(data.words.len()) {
+            return Err(Error::InvalidData("invalid packed vector"));
+        }
+
+        Ok(PackedVec {
+            width: data.width,
+            len: data.len,
+            words: data.words,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn get_and_set() {
+        for width in 1..65 {
+            let mut v = PackedVec::new(width, 100);
+            let max = v.max_value();
+            for i in 0..100 {
+                v.set(i, (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) & max);
+            }
+
+            for i in 0..100 {
+                assert_eq!(
+                    v.get(i),
+                    (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) & max
+                );
+            }
+
+            v.set(50, max);
+            assert_eq!(v.get(49), 49u64.wrapping_mul(0x9e37_79b9_7f4a_7c15) & max);
+            assert_eq!(v.get(50), max);
+            assert_eq!(v.get(51), 51u64.wrapping_mul(0x9e37_79b9_7f4a_7c15) & max);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let mut v = PackedVec::new(6, 20);
+        v.set(3, 42);
+        let json = ::serde_json::to_string(&v).unwrap();
+        assert_eq!(::serde_json::from_str::<PackedVec>(&json).unwrap(), v);
+
+        for json in &[
+            r#"{"width":0,"len":20,"words":[0,0]}"#,
+            r#"{"width":65,"len":20,"words":[0,0]}"#,
+            r#"{"width":6,"len":20,"words":[0]}"#,
+        ] {
+            assert!(::serde_json::from_str::<PackedVec>(json).is_err());
+        }
+    }
+
+    #[test]
+    fn memory_usage() {
+        assert_eq!(PackedVec::new(6, 16384).memory_usage(), 12288);
+    }
+}
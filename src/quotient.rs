@@ -0,0 +1,833 @@
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::mem;
+
+use codec;
+use error::Error;
+use hash::{fingerprint, SipBuildHasher};
+use packed::PackedVec;
+use sketch::{Estimator, Mergeable, Sketch};
+
+/// A quotient filter is a probabilistic multiset which stores a fingerprint of each element in a
+/// compact hash table, as described in [Don't Thrash: How to Cache Your Hash on
+/// Flash](https://www.vldb.org/pvldb/vol5/p1627_michaelabender_vldb2012.pdf) by Bender et al.
+///
+/// Each fingerprint is split into a quotient, which is its home slot, and a remainder, which is
+/// stored in the table along with three metadata bits. Since the fingerprints are stored in sorted
+/// order, a filter can double its capacity by moving one bit of each remainder into its quotient,
+/// without rehashing the original elements, and two filters can be merged with a single sequential
+/// pass. Growing leaves the fingerprints the same size, so the probability of false positives
+/// increases with the number of elements.
+///
+/// Duplicates are stored as repeated fingerprints, so a filter can count how many times an element
+/// was inserted and supports removing elements, but each copy takes up a slot.
+///
+/// ```
+/// use sketchy::QuotientFilter;
+///
+/// let mut filter = QuotientFilter::new(1000, 0.01);
+///
+/// filter.insert("one");
+/// filter.insert("one");
+/// filter.insert("two");
+/// assert_eq!(filter.count(&"one"), 2);
+///
+/// filter.grow().unwrap();
+/// assert!(filter.contains(&"two"));
+///
+/// assert!(filter.remove(&"one"));
+/// assert_eq!(filter.count(&"one"), 1);
+/// ```
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        bound(
+            serialize = "S: ::serde::Serialize",
+            deserialize = "E: Hash, S: ::serde::Deserialize<'de> + BuildHasher"
+        ),
+        try_from = "QuotientFilterData<S>"
+    )
+)]
+pub struct QuotientFilter<E, S = SipBuildHasher> {
+    quotient_bits: usize,
+    remainder_bits: usize,
+    len: usize,
+    slots: PackedVec,
+    hasher: S,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    marker: PhantomData<E>,
+}
+
+/// Set in a slot if some fingerprint has the slot's index as its quotient.
+const OCCUPIED: u64 = 1;
+
+/// Set in a slot if it holds a fingerprint which isn't the first of its run.
+const CONTINUATION: u64 = 2;
+
+/// Set in a slot if it holds a fingerprint which isn't in its home slot.
+const SHIFTED: u64 = 4;
+
+/// The number of metadata bits in a slot.
+const METADATA_BITS: usize = 3;
+
+/// The maximum fraction of home slots which may be used, beyond which runs get too long.
+const MAX_LOAD: f64 = 0.9;
+
+/// The largest number of quotient bits.
+const MAX_QUOTIENT_BITS: usize = 40;
+
+/// A fingerprint's quotient and remainder.
+type Entry = (usize, u64);
+
+impl<E: Hash> QuotientFilter<E> {
+    /// Creates a new `QuotientFilter` instance, tuned for a population of `n` elements with the
+    /// given upper bound of the probability of false positives.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the probability isn't in the range `(0, 1)` or the filter would be too large.
+    pub fn new(n: usize, max_false_pos_prob: f64) -> QuotientFilter<E> {
+        match QuotientFilter::try_new(n, max_false_pos_prob) {
+            Ok(qf) => qf,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Creates a new `QuotientFilter` instance, tuned for a population of `n` elements with the
+    /// given upper bound of the probability of false positives, or returns an error if the
+    /// probability isn't in the range `(0, 1)` or the filter would be too large.
+    pub fn try_new(n: usize, max_false_pos_prob: f64) -> Result<QuotientFilter<E>, Error> {
+        QuotientFilter::try_with_hasher(n, max_false_pos_prob, SipBuildHasher::default())
+    }
+
+    /// Returns the filter encoded in a compact, versioned binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = codec::header(codec::QUOTIENT_FILTER, codec::HASH_SIPHASH24);
+        self.hasher.encode(&mut buf);
+        buf.push(self.quotient_bits as u8);
+        buf.push(self.remainder_bits as u8);
+        codec::put_varint(&mut buf, self.len as u64);
+        for &w in self.slots.words() {
+            codec::put_u64(&mut buf, w);
+        }
+        codec::finish(buf)
+    }
+
+    /// Decodes a filter from the format produced by `to_bytes`, returning an error if the data is
+    /// corrupt or isn't an encoded `QuotientFilter`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<QuotientFilter<E>, Error> {
+        let mut input = codec::open(bytes, codec::QUOTIENT_FILTER, codec::HASH_SIPHASH24)?;
+        let hasher = SipBuildHasher::decode(&mut input)?;
+        let quotient_bits = codec::get_u8(&mut input)? as usize;
+        let remainder_bits = codec::get_u8(&mut input)? as usize;
+        let len = codec::get_varint(&mut input)? as usize;
+        if validate(quotient_bits, remainder_bits).is_err() {
+            return Err(Error::InvalidData("invalid fingerprint size"));
+        }
+
+        // Each word takes eight bytes.
+        let width = remainder_bits + METADATA_BITS;
+        let size = table_size(quotient_bits);
+        if (size * width).div_ceil(64) > input.len() / mem::size_of::<u64>() {
+            return Err(Error::Truncated);
+        }
+
+        let mut slots = PackedVec::new(width, size);
+        for w in slots.words_mut() {
+            *w = codec::get_u64(&mut input)?;
+        }
+        codec::close(input)?;
+
+        let mut qf = QuotientFilter::<E> {
+            quotient_bits,
+            remainder_bits,
+            len,
+            slots,
+            hasher,
+            marker: PhantomData,
+        };
+        qf.check()?;
+        Ok(qf)
+    }
+}
+
+impl<E: Hash, S: BuildHasher> QuotientFilter<E, S> {
+    /// Creates a new `QuotientFilter` instance, tuned for a population of `n` elements with the
+    /// given upper bound of the probability of false positives, which hashes elements with the
+    /// given hasher.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the probability isn't in the range `(0, 1)` or the filter would be too large.
+    pub fn with_hasher(n: usize, max_false_pos_prob: f64, hasher: S) -> QuotientFilter<E, S> {
+        match QuotientFilter::try_with_hasher(n, max_false_pos_prob, hasher) {
+            Ok(qf) => qf,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Creates a new `QuotientFilter` instance, tuned for a population of `n` elements with the
+    /// given upper bound of the probability of false positives, which hashes elements with the
+    /// given hasher. Returns an error if the probability isn't in the range `(0, 1)` or the filter
+    /// would be too large.
+    pub fn try_with_hasher(
+        n: usize,
+        max_false_pos_prob: f64,
+        hasher: S,
+    ) -> Result<QuotientFilter<E, S>, Error> {
+        if !(max_false_pos_prob > 0.0 && max_false_pos_prob < 1.0) {
+            return Err(Error::InvalidErrorRate(max_false_pos_prob));
+        }
+
+        // With a load of α, a query matches a remainder in its home run with a probability of
+        // about α / 2^r, which is less than p if 2^r >= 1 / p.
+        let quotient_bits = (n as f64 / MAX_LOAD).log2().ceil().max(1.0) as usize;
+        let remainder_bits = (1.0 / max_false_pos_prob).log2().ceil().max(1.0) as usize;
+        QuotientFilter::with_fingerprint_size_and_hasher(quotient_bits, remainder_bits, hasher)
+    }
+
+    /// Creates a new, empty `QuotientFilter` with `2^quotient_bits` home slots, each of which
+    /// stores a remainder of the given size, and which hashes elements with the given hasher.
+    /// Returns an error if either size is zero or their sum is more than 64.
+    pub fn with_fingerprint_size_and_hasher(
+        quotient_bits: usize,
+        remainder_bits: usize,
+        hasher: S,
+    ) -> Result<QuotientFilter<E, S>, Error> {
+        validate(quotient_bits, remainder_bits)?;
+        Ok(QuotientFilter::<E, S> {
+            quotient_bits,
+            remainder_bits,
+            len: 0,
+            slots: PackedVec::new(remainder_bits + METADATA_BITS, table_size(quotient_bits)),
+            hasher,
+            marker: PhantomData,
+        })
+    }
+
+    /// Returns the hasher used to hash elements.
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    /// Returns the number of bits of each fingerprint which pick its home slot.
+    pub fn quotient_bits(&self) -> usize {
+        self.quotient_bits
+    }
+
+    /// Returns the number of bits of each fingerprint which are stored in the table.
+    pub fn remainder_bits(&self) -> usize {
+        self.remainder_bits
+    }
+
+    /// Returns the number of elements in the filter, including duplicates.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the filter contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of elements the filter can hold before it must grow.
+    pub fn capacity(&self) -> usize {
+        ((1usize << self.quotient_bits) as f64 * MAX_LOAD) as usize
+    }
+
+    /// Returns the expected probability of false positives at the filter's current size.
+    pub fn false_pos_prob(&self) -> f64 {
+        let load = self.len as f64 / (1u64 << self.quotient_bits) as f64;
+        1.0 - (-load / (1u64 << self.remainder_bits) as f64).exp()
+    }
+
+    /// Adds a value to the set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the filter is full.
+    pub fn insert(&mut self, e: E) {
+        if let Err(e) = self.try_insert(e) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Adds a value to the set, or returns `Error::Full` if the filter is at capacity and must
+    /// grow first.
+    pub fn try_insert(&mut self, e: E) -> Result<(), Error> {
+        if self.len >= self.capacity() {
+            return Err(Error::Full);
+        }
+
+        let entry = self.locate(&e);
+        let (start, mut entries) = self.region(entry.0);
+        let old = entries.len();
+        let i = entries.binary_search(&entry).unwrap_or_else(|i| i);
+        entries.insert(i, entry);
+        self.rewrite(start, old, &entries)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Returns `true` if the set probably contains the given element.
+    pub fn contains(&self, e: &E) -> bool {
+        self.count(e) > 0
+    }
+
+    /// Returns the number of times the given element was probably inserted. This is never less
+    /// than the true count, but may be more if other elements have the same fingerprint.
+    pub fn count(&self, e: &E) -> u64 {
+        let entry = self.locate(e);
+        let (_, entries) = self.region(entry.0);
+        entries.iter().filter(|&&x| x == entry).count() as u64
+    }
+
+    /// Removes one copy of the given element from the set, returning `true` if its fingerprint was
+    /// found. The element must have been inserted; removing other elements may cause false
+    /// negatives.
+    pub fn remove(&mut self, e: &E) -> bool {
+        let entry = self.locate(e);
+        let (start, mut entries) = self.region(entry.0);
+        let old = entries.len();
+        match entries.binary_search(&entry) {
+            Ok(i) => {
+                entries.remove(i);
+                self.rewrite(start, old, &entries)
+                    .expect("removal can't overflow the table");
+                self.len -= 1;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Doubles the capacity of the filter by moving the highest bit of each remainder into its
+    /// quotient. Returns an error and leaves the filter unmodified if the remainders have only one
+    /// bit left or the filter would be too large.
+    pub fn grow(&mut self) -> Result<(), Error> {
+        let (quotient_bits, remainder_bits) = (self.quotient_bits + 1, self.remainder_bits - 1);
+        validate(quotient_bits, remainder_bits)?;
+
+        let mask = (1 << remainder_bits) - 1;
+        let entries: Vec<Entry> = self
+            .entries()
+            .into_iter()
+            .map(|(q, r)| ((q << 1) | (r >> remainder_bits) as usize, r & mask))
+            .collect();
+
+        let slots = PackedVec::new(remainder_bits + METADATA_BITS, table_size(quotient_bits));
+        let old = mem::replace(&mut self.slots, slots);
+        if let Err(e) = self.rewrite(0, 0, &entries) {
+            self.slots = old;
+            return Err(e);
+        }
+        self.quotient_bits = quotient_bits;
+        self.remainder_bits = remainder_bits;
+        Ok(())
+    }
+
+    /// Merges the contents of the given `QuotientFilter` into `self`, adding the counts of
+    /// elements in both.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the filters have different fingerprint sizes or hashers, or if the merged filter
+    /// would be over capacity.
+    pub fn merge(&mut self, other: &QuotientFilter<E, S>) {
+        if let Err(e) = self.try_merge(other) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Merges the contents of the given `QuotientFilter` into `self`, adding the counts of
+    /// elements in both. Both filters' fingerprints are read in sorted order and written back in a
+    /// single pass. Returns an error and leaves `self` unmodified if the filters have different
+    /// fingerprint sizes or hashers, or if the merged filter would be over capacity; a smaller
+    /// filter can be grown to match a larger one first.
+    pub fn try_merge(&mut self, other: &QuotientFilter<E, S>) -> Result<(), Error> {
+        self.check_compatible(other)?;
+        if self.len + other.len > self.capacity() {
+            return Err(Error::Full);
+        }
+
+        let (a, b) = (self.entries(), other.entries());
+        let mut merged = Vec::with_capacity(a.len() + b.len());
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() && j < b.len() {
+            if a[i] <= b[j] {
+                merged.push(a[i]);
+                i += 1;
+            } else {
+                merged.push(b[j]);
+                j += 1;
+            }
+        }
+        merged.extend_from_slice(&a[i..]);
+        merged.extend_from_slice(&b[j..]);
+
+        let slots = PackedVec::new(self.slots.width(), self.slots.len());
+        let old = mem::replace(&mut self.slots, slots);
+        if let Err(e) = self.rewrite(0, 0, &merged) {
+            self.slots = old;
+            return Err(e);
+        }
+        self.len += other.len;
+        Ok(())
+    }
+
+    /// Clears the filter, removing all elements from the set.
+    pub fn clear(&mut self) {
+        for w in self.slots.words_mut() {
+            *w = 0;
+        }
+        self.len = 0;
+    }
+
+    /// Returns the approximate amount of memory used by the filter, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>() + self.slots.memory_usage()
+    }
+
+    /// Checks that a decoded or deserialized filter, whose table has the right size for its
+    /// fingerprints, holds exactly the table which its fingerprints would produce.
+    fn check(&mut self) -> Result<(), Error> {
+        let entries = self
+            .scan(0, false)
+            .ok_or(Error::InvalidData("invalid metadata"))?;
+        if entries.len() != self.len
+            || entries.iter().any(|&(q, _)| q >> self.quotient_bits != 0)
+            || entries.windows(2).any(|w| w[0] > w[1])
+        {
+            return Err(Error::InvalidData("invalid fingerprints"));
+        }
+
+        let empty = PackedVec::new(self.slots.width(), self.slots.len());
+        let slots = mem::replace(&mut self.slots, empty);
+        if self.rewrite(0, 0, &entries).is_err() || self.slots != slots {
+            return Err(Error::InvalidData("invalid metadata"));
+        }
+        Ok(())
+    }
+
+    /// Returns the quotient and remainder of the element's fingerprint, which are the top bits of
+    /// its hash.
+    #[inline]
+    fn locate(&self, e: &E) -> Entry {
+        let h = self.hasher.hash_one(e) >> (64 - self.quotient_bits - self.remainder_bits);
+        let mask = (1 << self.remainder_bits) - 1;
+        ((h >> self.remainder_bits) as usize, h & mask)
+    }
+
+    /// Returns all fingerprints in sorted order.
+    fn entries(&self) -> Vec<Entry> {
+        self.scan(0, false).expect("invalid quotient filter")
+    }
+
+    /// Returns the start of the cluster which contains the given home slot and the sorted
+    /// fingerprints stored from there to the next empty slot, which are the only ones whose slots
+    /// may change when a fingerprint with that quotient is added or removed.
+    fn region(&self, quotient: usize) -> (usize, Vec<Entry>) {
+        let mut start = quotient;
+        while start > 0 && self.slots.get(start) & SHIFTED != 0 {
+            start -= 1;
+        }
+        (
+            start,
+            self.scan(start, true).expect("invalid quotient filter"),
+        )
+    }
+
+    /// Returns the fingerprints stored in the table from the given slot, which must start a
+    /// cluster, to the end of the table, or to the next empty slot if `stop` is `true`. Each run
+    /// belongs to the lowest occupied home slot which doesn't have a run yet. Returns `None` if the
+    /// metadata is inconsistent.
+    fn scan(&self, start: usize, stop: bool) -> Option<Vec<Entry>> {
+        let mut entries = Vec::new();
+        let mut runs = VecDeque::new();
+        let mut quotient = 0;
+        for i in start..self.slots.len() {
+            let v = self.slots.get(i);
+            if v & OCCUPIED != 0 {
+                runs.push_back(i);
+            }
+
+            if v & (OCCUPIED | CONTINUATION | SHIFTED) == 0 {
+                if stop {
+                    break;
+                }
+                continue;
+            }
+
+            if v & CONTINUATION == 0 {
+                quotient = runs.pop_front()?;
+            }
+            entries.push((quotient, v >> METADATA_BITS));
+        }
+
+        if runs.is_empty() {
+            Some(entries)
+        } else {
+            None
+        }
+    }
+
+    /// Replaces the `old` slots starting at `start` with the given sorted fingerprints, each of
+    /// which is stored in the first free slot at or after its home slot. Returns `Error::Full` and
+    /// leaves the table unmodified if the fingerprints would overflow the end of the table.
+    fn rewrite(&mut self, start: usize, old: usize, entries: &[Entry]) -> Result<(), Error> {
+        let end = entries.iter().fold(start, |end, &(q, _)| end.max(q) + 1);
+        if end > self.slots.len() {
+            return Err(Error::Full);
+        }
+
+        for i in start..start + old {
+            self.slots.set(i, 0);
+        }
+
+        let mut next = start;
+        let mut prev = None;
+        for &(quotient, remainder) in entries {
+            let i = next.max(quotient);
+            let mut v = (remainder << METADATA_BITS) | (self.slots.get(i) & OCCUPIED);
+            if i != quotient {
+                v |= SHIFTED;
+            }
+            if prev == Some(quotient) {
+                v |= CONTINUATION;
+            }
+            self.slots.set(i, v);
+            self.slots
+                .set(quotient, self.slots.get(quotient) | OCCUPIED);
+            next = i + 1;
+            prev = Some(quotient);
+        }
+        Ok(())
+    }
+}
+
+impl<E: Hash, S: BuildHasher> Sketch<E> for QuotientFilter<E, S> {
+    fn insert(&mut self, e: E) {
+        QuotientFilter::insert(self, e)
+    }
+
+    fn clear(&mut self) {
+        QuotientFilter::clear(self)
+    }
+
+    fn memory_usage(&self) -> usize {
+        QuotientFilter::memory_usage(self)
+    }
+}
+
+impl<E: Hash, S: BuildHasher> Mergeable for QuotientFilter<E, S> {
+    fn check_compatible(&self, other: &Self) -> Result<(), Error> {
+        if self.quotient_bits != other.quotient_bits || self.remainder_bits != other.remainder_bits
+        {
+            return Err(Error::Incompatible("different parameters"));
+        }
+
+        if fingerprint(&self.hasher) != fingerprint(&other.hasher) {
+            return Err(Error::HasherMismatch);
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: &Self) -> Result<(), Error> {
+        self.try_merge(other)
+    }
+}
+
+impl<E: Hash, S: BuildHasher> Estimator<E> for QuotientFilter<E, S> {
+    type Estimate = bool;
+
+    fn estimate(&self, e: &E) -> bool {
+        self.contains(e)
+    }
+}
+
+/// Returns the number of slots in a table with the given number of quotient bits. Runs which start
+/// near the end spill over into extra slots rather than wrapping around, so that the fingerprints
+/// are stored in sorted order.
+fn table_size(quotient_bits: usize) -> usize {
+    let home = 1usize << quotient_bits;
+    home + 64 + 10 * (home as f64).sqrt() as usize
+}
+
+fn validate(quotient_bits: usize, remainder_bits: usize) -> Result<(), Error> {
+    if quotient_bits == 0 || quotient_bits > MAX_QUOTIENT_BITS {
+        return Err(Error::InvalidParameter("too many elements"));
+    }
+
+    if remainder_bits == 0 || quotient_bits + remainder_bits > 64 {
+        return Err(Error::InvalidParameter("invalid fingerprint size"));
+    }
+    Ok(())
+}
+
+/// The fields of a deserialized `QuotientFilter`, which are checked before use.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct QuotientFilterData<S> {
+    quotient_bits: usize,
+    remainder_bits: usize,
+    len: usize,
+    slots: PackedVec,
+    hasher: S,
+}
+
+#[cfg(feature = "serde")]
+impl<E: Hash, S: BuildHasher> ::std::convert::TryFrom<QuotientFilterData<S>>
+    for QuotientFilter<E, S>
+{
+    type Error = Error;
+
+    fn try_from(data: QuotientFilterData<S>) -> Result<QuotientFilter<E, S>, Error> {
+        if validate(data.quotient_bits, data.remainder_bits).is_err() {
+            return Err(Error::InvalidData("invalid fingerprint size"));
+        }
+
+        if data.slots.width() != data.remainder_bits + METADATA_BITS
+            || data.slots.len() != table_size(data.quotient_bits)
+        {
+            return Err(Error::InvalidData("inconsistent table size"));
+        }
+
+        let mut qf = QuotientFilter {
+            quotient_bits: data.quotient_bits,
+            remainder_bits: data.remainder_bits,
+            len: data.len,
+            slots: data.slots,
+            hasher: data.hasher,
+            marker: PhantomData,
+        };
+        qf.check()?;
+        Ok(qf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_and_query() {
+        let mut qf = QuotientFilter::new(100, 0.01);
+        assert!(qf.is_empty());
+        qf.insert(100);
+        qf.insert(400);
+
+        assert_eq!(qf.len(), 2);
+        assert!(qf.contains(&100));
+        assert!(qf.contains(&400));
+        assert!(!qf.contains(&500));
+    }
+
+    #[test]
+    fn count_and_remove() {
+        let mut qf = QuotientFilter::new(100, 0.01);
+        for _ in 0..3 {
+            qf.insert("one");
+        }
+        qf.insert("two");
+        assert_eq!(qf.count(&"one"), 3);
+        assert_eq!(qf.count(&"two"), 1);
+
+        assert!(qf.remove(&"one"));
+        assert_eq!(qf.count(&"one"), 2);
+        assert!(qf.remove(&"two"));
+        assert!(!qf.remove(&"two"));
+        assert!(!qf.contains(&"two"));
+        assert_eq!(qf.len(), 2);
+    }
+
+    #[test]
+    fn crowded() {
+        // With only a few home slots, every insertion and removal shifts long runs around.
+        let mut qf =
+            QuotientFilter::with_fingerprint_size_and_hasher(4, 12, SipBuildHasher::default())
+                .unwrap();
+        let n = qf.capacity() as u64;
+        for i in 0..n {
+            qf.insert(i);
+            assert!((0..=i).all(|j| qf.contains(&j)));
+        }
+        assert_eq!(qf.try_insert(n), Err(Error::Full));
+
+        for i in (0..n).filter(|i| i % 2 == 0) {
+            assert!(qf.remove(&i));
+        }
+        assert!((0..n).filter(|i| i % 2 == 1).all(|i| qf.contains(&i)));
+        assert_eq!(qf.len(), (n / 2) as usize);
+        assert_eq!(qf.scan(0, false).unwrap().len(), qf.len());
+    }
+
+    #[test]
+    fn false_positive_rate() {
+        let p = 0.01;
+        let n = 10_000;
+        let mut qf = QuotientFilter::new(n, p);
+        for i in 0..n as u64 {
+            qf.insert(i);
+        }
+        assert!((0..n as u64).all(|i| qf.contains(&i)));
+        assert!(qf.false_pos_prob() < p);
+
+        let trials = 100_000;
+        let false_positives = (n as u64..(n + trials) as u64)
+            .filter(|i| qf.contains(i))
+            .count();
+        assert!((false_positives as f64 / trials as f64) < p);
+    }
+
+    #[test]
+    fn grow() {
+        let mut qf = QuotientFilter::new(100, 0.01);
+        let (q, r) = (qf.quotient_bits(), qf.remainder_bits());
+        for i in 0..qf.capacity() as u64 {
+            qf.insert(i);
+        }
+        assert_eq!(qf.try_insert(1000), Err(Error::Full));
+
+        qf.grow().unwrap();
+        assert_eq!((qf.quotient_bits(), qf.remainder_bits()), (q + 1, r - 1));
+        assert!((0..qf.len() as u64).all(|i| qf.contains(&i)));
+        qf.insert(1000);
+        assert!(qf.contains(&1000));
+
+        let mut qf = QuotientFilter::<u64>::with_fingerprint_size_and_hasher(
+            4,
+            1,
+            SipBuildHasher::default(),
+        )
+        .unwrap();
+        assert!(qf.grow().is_err());
+        assert_eq!(qf.remainder_bits(), 1);
+    }
+
+    #[test]
+    fn merge() {
+        let mut qf1 = QuotientFilter::new(1000, 0.01);
+        let mut qf2 = QuotientFilter::new(1000, 0.01);
+        for i in 0..400 {
+            qf1.insert(i);
+            qf2.insert(i + 200);
+        }
+
+        qf1.merge(&qf2);
+        assert_eq!(qf1.len(), 800);
+        assert!((0..600).all(|i| qf1.contains(&i)));
+        assert_eq!(qf1.count(&100), 1);
+        assert_eq!(qf1.count(&300), 2);
+
+        let mut qf3 = QuotientFilter::new(1000, 0.01);
+        for i in 0..1100 {
+            qf3.insert(i);
+        }
+        assert_eq!(qf1.try_merge(&qf3), Err(Error::Full));
+        assert_eq!(qf1.len(), 800);
+        assert_eq!(qf1.count(&300), 2);
+
+        assert_eq!(
+            qf1.try_merge(&QuotientFilter::new(100, 0.01)),
+            Err(Error::Incompatible("different parameters"))
+        );
+        assert_eq!(
+            qf1.try_merge(&QuotientFilter::with_hasher(
+                1000,
+                0.01,
+                SipBuildHasher::new(1, 2)
+            )),
+            Err(Error::HasherMismatch)
+        );
+    }
+
+    #[test]
+    fn merge_after_grow() {
+        let mut small = QuotientFilter::new(100, 0.001);
+        let mut large = QuotientFilter::with_fingerprint_size_and_hasher(
+            small.quotient_bits() + 1,
+            small.remainder_bits() - 1,
+            SipBuildHasher::default(),
+        )
+        .unwrap();
+        small.insert("one");
+        large.insert("two");
+
+        small.grow().unwrap();
+        assert_eq!(small.quotient_bits(), large.quotient_bits());
+        large.merge(&small);
+        assert!(large.contains(&"one"));
+        assert!(large.contains(&"two"));
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        assert!(QuotientFilter::<u64>::try_new(100, 0.0).is_err());
+        assert!(QuotientFilter::<u64>::try_new(100, 1.0).is_err());
+        assert!(QuotientFilter::<u64>::try_new(usize::MAX, 0.01).is_err());
+        assert!(QuotientFilter::<u64>::try_new(100, 1e-30).is_err());
+    }
+
+    #[test]
+    fn clear() {
+        let mut qf = QuotientFilter::new(100, 0.01);
+        qf.insert(100);
+        qf.clear();
+        assert!(!qf.contains(&100));
+        assert!(qf.is_empty());
+    }
+
+    #[test]
+    fn to_and_from_bytes() {
+        let mut qf = QuotientFilter::new(1000, 0.01);
+        for i in 0..500 {
+            qf.insert(i);
+        }
+
+        let bytes = qf.to_bytes();
+        let decoded = QuotientFilter::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.slots, qf.slots);
+        assert_eq!(decoded.len(), 500);
+        assert!((0..500).all(|i| decoded.contains(&i)));
+
+        // Mark the last slot, which is empty, as shifted.
+        let last = qf.slots.len() - 1;
+        qf.slots.set(last, SHIFTED);
+        assert_eq!(
+            QuotientFilter::<i32>::from_bytes(&qf.to_bytes()).err(),
+            Some(Error::InvalidData("invalid metadata"))
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let mut qf = QuotientFilter::new(100, 0.01);
+        qf.insert(100);
+
+        let json = ::serde_json::to_string(&qf).unwrap();
+        let decoded: QuotientFilter<i32> = ::serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.slots, qf.slots);
+        assert!(decoded.contains(&100));
+
+        let remainder_bits = format!("\"remainder_bits\":{}", qf.remainder_bits());
+        for &(from, to) in &[
+            (remainder_bits.as_str(), "\"remainder_bits\":0"),
+            (remainder_bits.as_str(), "\"remainder_bits\":60"),
+            ("\"len\":1", "\"len\":2"),
+        ] {
+            let corrupt = json.replacen(from, to, 1);
+            assert_ne!(corrupt, json);
+            assert!(::serde_json::from_str::<QuotientFilter<i32>>(&corrupt).is_err());
+        }
+
+        // A shifted fingerprint with no occupied home slot.
+        let json = ::serde_json::to_string(&QuotientFilter::<i32>::new(100, 0.01)).unwrap();
+        let corrupt = json.replacen("\"words\":[0,", "\"words\":[4,", 1);
+        assert_ne!(corrupt, json);
+        assert!(::serde_json::from_str::<QuotientFilter<i32>>(&json).is_ok());
+        assert!(::serde_json::from_str::<QuotientFilter<i32>>(&corrupt).is_err());
+    }
+}
@@ -1,5 +1,11 @@
+use std::mem;
+
 use rand::{thread_rng, Rng};
 
+use codec::{self, Codec};
+use error::Error;
+use sketch::Sketch;
+
 /// A reservoir sample maintains a sample of K elements, selected uniformly and at random from a
 /// stream. This implementation uses [Vitter's Algorithm
 /// R](http://www.cs.umd.edu/~samir/498/vitter.pdf) with thread-local PRNGs.
@@ -15,27 +21,50 @@ use rand::{thread_rng, Rng};
 ///
 /// assert_eq!(res.elements().len(), 2);
 /// ```
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "ReservoirSampleData<E>")
+)]
 pub struct ReservoirSample<E> {
+    size: usize,
     count: usize,
     elements: Vec<E>,
 }
 
 impl<E: Clone> ReservoirSample<E> {
     /// Returns a new `ReservoirSample` of the given size.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size is zero.
     pub fn new(size: usize) -> ReservoirSample<E> {
-        ReservoirSample::<E> {
+        match ReservoirSample::try_new(size) {
+            Ok(sample) => sample,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Returns a new `ReservoirSample` of the given size, or an error if the size is zero.
+    pub fn try_new(size: usize) -> Result<ReservoirSample<E>, Error> {
+        if size == 0 {
+            return Err(Error::InvalidParameter("size must be positive"));
+        }
+
+        Ok(ReservoirSample::<E> {
+            size,
             count: 0,
             elements: Vec::with_capacity(size),
-        }
+        })
     }
 
     /// Inserts the given element into the sample.
     pub fn insert(&mut self, e: E) {
-        if self.count < self.elements.capacity() {
+        if self.count < self.size {
             self.elements.push(e);
         } else {
             let idx = thread_rng().gen_range(0, self.count);
-            if idx < self.elements.capacity() {
+            if idx < self.size {
                 self.elements[idx] = e;
             }
         }
@@ -46,6 +75,99 @@ impl<E: Clone> ReservoirSample<E> {
     pub fn elements(self) -> Vec<E> {
         self.elements.clone()
     }
+
+    /// Removes all elements from the sample.
+    pub fn clear(&mut self) {
+        self.count = 0;
+        self.elements.clear();
+    }
+
+    /// Returns the approximate amount of memory used by the sample, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>() + self.elements.capacity() * mem::size_of::<E>()
+    }
+}
+
+impl<E: Clone> Sketch<E> for ReservoirSample<E> {
+    fn insert(&mut self, e: E) {
+        ReservoirSample::insert(self, e)
+    }
+
+    fn clear(&mut self) {
+        ReservoirSample::clear(self)
+    }
+
+    fn memory_usage(&self) -> usize {
+        ReservoirSample::memory_usage(self)
+    }
+}
+
+impl<E: Clone + Codec> ReservoirSample<E> {
+    /// Returns the sample encoded in a compact, versioned binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = codec::header(codec::RESERVOIR_SAMPLE, codec::HASH_NONE);
+        codec::put_varint(&mut buf, self.size as u64);
+        codec::put_varint(&mut buf, self.count as u64);
+        codec::put_varint(&mut buf, self.elements.len() as u64);
+        for e in self.elements.iter() {
+            e.encode(&mut buf);
+        }
+        codec::finish(buf)
+    }
+
+    /// Decodes a sample from the format produced by `to_bytes`, returning an error if the data is
+    /// corrupt or isn't an encoded `ReservoirSample`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<ReservoirSample<E>, Error> {
+        let mut input = codec::open(bytes, codec::RESERVOIR_SAMPLE, codec::HASH_NONE)?;
+        let size = codec::get_varint(&mut input)? as usize;
+        let count = codec::get_varint(&mut input)? as usize;
+        let len = codec::get_len(&mut input)?;
+        check_sizes(size, count, len)?;
+
+        let mut elements = Vec::with_capacity(len);
+        for _ in 0..len {
+            elements.push(E::decode(&mut input)?);
+        }
+        codec::close(input)?;
+
+        Ok(ReservoirSample::<E> {
+            size,
+            count,
+            elements,
+        })
+    }
+}
+
+/// Returns an error if a decoded sample of the given size, which has seen `count` elements, can't
+/// hold `len` elements.
+fn check_sizes(size: usize, count: usize, len: usize) -> Result<(), Error> {
+    if size == 0 || len > size || len != count.min(size) {
+        return Err(Error::InvalidData("inconsistent sample size"));
+    }
+    Ok(())
+}
+
+/// The fields of a deserialized `ReservoirSample`, which are checked before use.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct ReservoirSampleData<E> {
+    size: usize,
+    count: usize,
+    elements: Vec<E>,
+}
+
+#[cfg(feature = "serde")]
+impl<E> ::std::convert::TryFrom<ReservoirSampleData<E>> for ReservoirSample<E> {
+    type Error = Error;
+
+    fn try_from(data: ReservoirSampleData<E>) -> Result<ReservoirSample<E>, Error> {
+        check_sizes(data.size, data.count, data.elements.len())?;
+        Ok(ReservoirSample::<E> {
+            size: data.size,
+            count: data.count,
+            elements: data.elements,
+        })
+    }
 }
 
 #[cfg(test)]
@@ -65,7 +187,65 @@ mod test {
         assert_eq!(elements.len(), 10);
 
         for &i in elements.iter() {
-            assert!(i >= 0 && i < 100);
+            assert!((0..100).contains(&i));
+        }
+    }
+
+    #[test]
+    fn invalid_size() {
+        assert_eq!(
+            ReservoirSample::<u8>::try_new(0).err(),
+            Some(Error::InvalidParameter("size must be positive"))
+        );
+    }
+
+    #[test]
+    fn to_and_from_bytes() {
+        let mut sample = ReservoirSample::new(10);
+
+        for i in 0..100 {
+            sample.insert(i.to_string());
+        }
+
+        let bytes = sample.to_bytes();
+        let decoded = ReservoirSample::<String>::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.count, 100);
+        assert_eq!(decoded.size, 10);
+        assert_eq!(decoded.elements(), sample.elements());
+
+        assert_eq!(
+            ReservoirSample::<u8>::from_bytes(&bytes).err(),
+            Some(Error::InvalidData("trailing bytes"))
+        );
+
+        // A huge sample size doesn't allocate space for it up front.
+        let mut huge = ReservoirSample::new(1);
+        huge.insert(1u8);
+        huge.size = usize::MAX;
+        let decoded = ReservoirSample::<u8>::from_bytes(&huge.to_bytes()).unwrap();
+        assert_eq!(decoded.size, usize::MAX);
+        assert!(decoded.memory_usage() < 1024);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let mut sample = ReservoirSample::new(10);
+        sample.insert("one".to_string());
+
+        let json = ::serde_json::to_string(&sample).unwrap();
+        let mut decoded: ReservoirSample<String> = ::serde_json::from_str(&json).unwrap();
+        for i in 0..100 {
+            decoded.insert(i.to_string());
+        }
+        assert_eq!(decoded.elements().len(), 10);
+
+        for json in &[
+            r#"{"size":0,"count":0,"elements":[]}"#,
+            r#"{"size":1,"count":2,"elements":["a","b"]}"#,
+            r#"{"size":2,"count":2,"elements":["a"]}"#,
+        ] {
+            assert!(::serde_json::from_str::<ReservoirSample<String>>(json).is_err());
         }
     }
 }
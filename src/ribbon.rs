@@ -0,0 +1,507 @@
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::mem;
+
+use codec;
+use error::Error;
+use hash::{mix, splitmix64, SipBuildHasher};
+use packed::PackedVec;
+use sketch::Estimator;
+
+/// A Ribbon filter is an immutable probabilistic set which is built once from a known set of keys,
+/// as described in [Ribbon filter: practically smaller than Bloom and
+/// Xor](https://arxiv.org/abs/2103.02515) by Dillinger and Walzer.
+///
+/// Each key is mapped to a random 64-bit band of a table, and the table is chosen by solving a
+/// banded linear system over GF(2) such that the XOR of the table's rows in each key's band is the
+/// key's fingerprint. This uses the standard (non-homogeneous) construction, so the probability
+/// of false positives is exactly `2^-r` for `r`-bit fingerprints, and the table has 5–15% more
+/// rows than keys, depending on the number of keys. That puts it much closer to the
+/// information-theoretic minimum of `log2(1/p)` bits per key than a `BloomFilter`, which needs
+/// about 44% more.
+///
+/// Filters are built with a `RibbonFilterBuilder`.
+///
+/// ```
+/// use sketchy::RibbonFilterBuilder;
+///
+/// let mut builder = RibbonFilterBuilder::new(0.01);
+/// builder.insert("one");
+/// builder.insert("two");
+///
+/// let filter = builder.build().unwrap();
+/// assert!(filter.contains(&"one"));
+/// ```
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        bound(
+            serialize = "S: ::serde::Serialize",
+            deserialize = "S: ::serde::Deserialize<'de>"
+        ),
+        try_from = "RibbonFilterData<S>"
+    )
+)]
+pub struct RibbonFilter<E, S = SipBuildHasher> {
+    seed: u64,
+    len: usize,
+    rows: PackedVec,
+    hasher: S,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    marker: PhantomData<E>,
+}
+
+/// Collects the keys of a `RibbonFilter`, then builds it.
+pub struct RibbonFilterBuilder<E, S = SipBuildHasher> {
+    fingerprint_bits: usize,
+    hashes: Vec<u64>,
+    hasher: S,
+    marker: PhantomData<E>,
+}
+
+/// The width of each key's band of rows.
+const WIDTH: usize = 64;
+
+/// The number of seeds tried before construction fails.
+const MAX_ATTEMPTS: usize = 64;
+
+/// The arbitrary seeds which derive a key's coefficients and fingerprint from its mixed hash.
+const COEFFICIENT_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+const FINGERPRINT_SEED: u64 = 0x6a09_e667_f3bc_c909;
+
+impl<E: Hash> RibbonFilterBuilder<E> {
+    /// Creates a new `RibbonFilterBuilder` for a filter with the given upper bound of the
+    /// probability of false positives.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the probability isn't in the range `[2^-32, 1)`.
+    pub fn new(max_false_pos_prob: f64) -> RibbonFilterBuilder<E> {
+        match RibbonFilterBuilder::try_new(max_false_pos_prob) {
+            Ok(b) => b,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Creates a new `RibbonFilterBuilder` for a filter with the given upper bound of the
+    /// probability of false positives, or returns an error if the probability isn't in the range
+    /// `[2^-32, 1)`.
+    pub fn try_new(max_false_pos_prob: f64) -> Result<RibbonFilterBuilder<E>, Error> {
+        RibbonFilterBuilder::try_with_hasher(max_false_pos_prob, SipBuildHasher::default())
+    }
+}
+
+impl<E: Hash, S: BuildHasher> RibbonFilterBuilder<E, S> {
+    /// Creates a new `RibbonFilterBuilder` for a filter with the given upper bound of the
+    /// probability of false positives, which hashes keys with the given hasher.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the probability isn't in the range `[2^-32, 1)`.
+    pub fn with_hasher(max_false_pos_prob: f64, hasher: S) -> RibbonFilterBuilder<E, S> {
+        match RibbonFilterBuilder::try_with_hasher(max_false_pos_prob, hasher) {
+            Ok(b) => b,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Creates a new `RibbonFilterBuilder` for a filter with the given upper bound of the
+    /// probability of false positives, which hashes keys with the given hasher. Returns an error
+    /// if the probability isn't in the range `[2^-32, 1)`.
+    pub fn try_with_hasher(
+        max_false_pos_prob: f64,
+        hasher: S,
+    ) -> Result<RibbonFilterBuilder<E, S>, Error> {
+        if !(max_false_pos_prob > 0.0 && max_false_pos_prob < 1.0) {
+            return Err(Error::InvalidErrorRate(max_false_pos_prob));
+        }
+
+        // Allow for rounding error, so that e.g. 2^-8 gets 8 bits rather than 9.
+        let bits = ((1.0 / max_false_pos_prob).log2() - 1e-9).ceil().max(1.0) as usize;
+        if bits > 32 {
+            return Err(Error::InvalidErrorRate(max_false_pos_prob));
+        }
+
+        Ok(RibbonFilterBuilder {
+            fingerprint_bits: bits,
+            hashes: Vec::new(),
+            hasher,
+            marker: PhantomData,
+        })
+    }
+
+    /// Adds a key to the set.
+    pub fn insert(&mut self, e: E) {
+        self.hashes.push(self.hasher.hash_one(e));
+    }
+
+    /// Returns the number of keys added so far, including duplicates.
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// Returns `true` if no keys have been added.
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Builds a `RibbonFilter` containing the keys. Returns an error if no solution could be found,
+    /// which is vanishingly unlikely.
+    pub fn build(mut self) -> Result<RibbonFilter<E, S>, Error> {
+        // Duplicate keys are harmless, since their equations are identical, but they needn't take
+        // up rows.
+        self.hashes.sort_unstable();
+        self.hashes.dedup();
+
+        let n = self.hashes.len();
+        let mut rows = if n == 0 {
+            0
+        } else {
+            WIDTH + (n as f64 * (1.0 + overhead(n))).ceil() as usize
+        };
+        let mut seed = 0;
+        for attempt in 0..MAX_ATTEMPTS {
+            seed = splitmix64(seed);
+
+            // Each failure makes it likelier that the table is just too small.
+            if attempt > 0 && attempt % 4 == 0 {
+                rows += rows / 64;
+            }
+
+            if let Some(solution) = solve(&self.hashes, seed, rows, self.fingerprint_bits) {
+                return Ok(RibbonFilter {
+                    seed,
+                    len: n,
+                    rows: solution,
+                    hasher: self.hasher,
+                    marker: PhantomData,
+                });
+            }
+        }
+        Err(Error::InvalidParameter("unable to construct filter"))
+    }
+}
+
+impl<E: Hash> RibbonFilter<E> {
+    /// Returns the filter encoded in a compact, versioned binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = codec::header(codec::RIBBON_FILTER, codec::HASH_SIPHASH24);
+        self.hasher.encode(&mut buf);
+        codec::put_u64(&mut buf, self.seed);
+        buf.push(self.rows.width() as u8);
+        codec::put_varint(&mut buf, self.len as u64);
+        codec::put_varint(&mut buf, self.rows.len() as u64);
+        for &w in self.rows.words() {
+            codec::put_u64(&mut buf, w);
+        }
+        codec::finish(buf)
+    }
+
+    /// Decodes a filter from the format produced by `to_bytes`, returning an error if the data is
+    /// corrupt or isn't an encoded `RibbonFilter`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<RibbonFilter<E>, Error> {
+        let mut input = codec::open(bytes, codec::RIBBON_FILTER, codec::HASH_SIPHASH24)?;
+        let hasher = SipBuildHasher::decode(&mut input)?;
+        let seed = codec::get_u64(&mut input)?;
+        let width = codec::get_u8(&mut input)? as usize;
+        let len = codec::get_varint(&mut input)? as usize;
+        let rows = codec::get_varint(&mut input)? as usize;
+        check_rows(width, len, rows)?;
+
+        // Each word takes eight bytes.
+        match rows.checked_mul(width) {
+            Some(bits) if bits.div_ceil(64) <= input.len() / mem::size_of::<u64>() => {}
+            _ => return Err(Error::Truncated),
+        }
+
+        let mut solution = PackedVec::new(width, rows);
+        for w in solution.words_mut() {
+            *w = codec::get_u64(&mut input)?;
+        }
+        codec::close(input)?;
+
+        Ok(RibbonFilter::<E> {
+            seed,
+            len,
+            rows: solution,
+            hasher,
+            marker: PhantomData,
+        })
+    }
+}
+
+impl<E: Hash, S: BuildHasher> RibbonFilter<E, S> {
+    /// Returns the hasher used to hash keys.
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    /// Returns the number of distinct keys in the filter.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the filter contains no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the size of the fingerprints, in bits.
+    pub fn fingerprint_bits(&self) -> usize {
+        self.rows.width()
+    }
+
+    /// Returns the probability of false positives.
+    pub fn false_pos_prob(&self) -> f64 {
+        0.5f64.powi(self.rows.width() as i32)
+    }
+
+    /// Returns `true` if the set probably contains the given element.
+    pub fn contains(&self, e: &E) -> bool {
+        if self.len == 0 {
+            return false;
+        }
+
+        let (start, mut coefficients, fingerprint) = equation(
+            self.hasher.hash_one(e),
+            self.seed,
+            self.rows.len(),
+            self.rows.width(),
+        );
+        let mut acc = 0;
+        while coefficients != 0 {
+            let j = coefficients.trailing_zeros() as usize;
+            acc ^= self.rows.get(start + j);
+            coefficients &= coefficients - 1;
+        }
+        acc == fingerprint
+    }
+
+    /// Returns the approximate amount of memory used by the filter, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>() + self.rows.memory_usage()
+    }
+}
+
+impl<E: Hash, S: BuildHasher> Estimator<E> for RibbonFilter<E, S> {
+    type Estimate = bool;
+
+    fn estimate(&self, e: &E) -> bool {
+        self.contains(e)
+    }
+}
+
+/// Checks the fingerprint size and number of rows of a decoded or deserialized filter of `len`
+/// keys, which has at least one band of rows for each key unless it's empty.
+fn check_rows(width: usize, len: usize, rows: usize) -> Result<(), Error> {
+    if !(1..=32).contains(&width) {
+        return Err(Error::InvalidData("invalid fingerprint size"));
+    }
+
+    if (len == 0) != (rows == 0) || (rows != 0 && rows < WIDTH.saturating_add(len)) {
+        return Err(Error::InvalidData("invalid number of rows"));
+    }
+    Ok(())
+}
+
+/// The fields of a deserialized `RibbonFilter`, which are checked before use.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct RibbonFilterData<S> {
+    seed: u64,
+    len: usize,
+    rows: PackedVec,
+    hasher: S,
+}
+
+#[cfg(feature = "serde")]
+impl<E, S> ::std::convert::TryFrom<RibbonFilterData<S>> for RibbonFilter<E, S> {
+    type Error = Error;
+
+    fn try_from(data: RibbonFilterData<S>) -> Result<RibbonFilter<E, S>, Error> {
+        check_rows(data.rows.width(), data.len, data.rows.len())?;
+        Ok(RibbonFilter {
+            seed: data.seed,
+            len: data.len,
+            rows: data.rows,
+            hasher: data.hasher,
+            marker: PhantomData,
+        })
+    }
+}
+
+/// Returns the fraction of extra rows which makes construction of a filter of `n` keys likely to
+/// succeed. With 64-row bands, the system is consistent with high probability once there are
+/// about `0.02 * log10(n) + 0.01` extra rows per key, so larger sets need slightly more space.
+fn overhead(n: usize) -> f64 {
+    (0.02 * (n as f64).log10() + 0.01).max(0.05)
+}
+
+/// Returns a key's equation: the first row of its band, which rows of the band are XORed, and the
+/// fingerprint they must XOR to. The first row is always included.
+#[inline]
+fn equation(h: u64, seed: u64, rows: usize, bits: usize) -> (usize, u64, u64) {
+    let h = mix(h, seed);
+    let start = ((u128::from(h) * (rows - WIDTH + 1) as u128) >> 64) as usize;
+    let coefficients = mix(h, COEFFICIENT_SEED) | 1;
+    let fingerprint = mix(h, FINGERPRINT_SEED) & (u64::MAX >> (64 - bits));
+    (start, coefficients, fingerprint)
+}
+
+/// Solves the system of the keys' equations, returning a table of rows which satisfies them or
+/// `None` if the system is inconsistent.
+///
+/// The system is reduced to echelon form as keys are added, by XORing each equation with the
+/// equation whose first coefficient is at the same row until it has a first coefficient at an
+/// unused row. The solution is then found by back substitution from the last row.
+fn solve(hashes: &[u64], seed: u64, rows: usize, bits: usize) -> Option<PackedVec> {
+    let mut coefficients = vec![0u64; rows];
+    let mut results = vec![0u64; rows];
+    for &h in hashes {
+        let (mut i, mut c, mut r) = equation(h, seed, rows, bits);
+        loop {
+            if coefficients[i] == 0 {
+                coefficients[i] = c;
+                results[i] = r;
+                break;
+            }
+
+            c ^= coefficients[i];
+            r ^= results[i];
+            if c == 0 {
+                // The equation is a combination of earlier ones, which is only consistent if
+                // the fingerprints agree.
+                if r != 0 {
+                    return None;
+                }
+                break;
+            }
+
+            let shift = c.trailing_zeros();
+            i += shift as usize;
+            c >>= shift;
+        }
+    }
+
+    let mut solution = PackedVec::new(bits, rows);
+    for i in (0..rows).rev() {
+        let mut c = coefficients[i] & !1;
+        let mut r = results[i];
+        while c != 0 {
+            let j = c.trailing_zeros() as usize;
+            r ^= solution.get(i + j);
+            c &= c - 1;
+        }
+        solution.set(i, r);
+    }
+    Some(solution)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bloomfilter::{best_buckets_and_k, BloomFilter, PROBS};
+
+    fn build(n: u64, p: f64) -> RibbonFilter<u64> {
+        let mut builder = RibbonFilterBuilder::new(p);
+        for i in 0..n {
+            builder.insert(i);
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn insert_and_query() {
+        for &n in &[0, 1, 2, 10, 1000, 100_000] {
+            let filter = build(n, 0.01);
+            assert_eq!(filter.len(), n as usize);
+            assert!((0..n).all(|i| filter.contains(&i)));
+        }
+        assert!(!build(0, 0.01).contains(&1));
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut builder = RibbonFilterBuilder::new(0.01);
+        builder.insert("one");
+        builder.insert("one");
+        builder.insert("two");
+        assert_eq!(builder.len(), 3);
+
+        let filter = builder.build().unwrap();
+        assert_eq!(filter.len(), 2);
+        assert!(filter.contains(&"one"));
+    }
+
+    #[test]
+    fn fingerprint_bits() {
+        assert_eq!(build(10, 0.01).fingerprint_bits(), 7);
+        assert_eq!(build(10, 1.0 / 256.0).fingerprint_bits(), 8);
+        assert_eq!(build(10, 0.5).fingerprint_bits(), 1);
+        assert!(RibbonFilterBuilder::<u64>::try_new(0.0).is_err());
+        assert!(RibbonFilterBuilder::<u64>::try_new(1.0).is_err());
+        assert!(RibbonFilterBuilder::<u64>::try_new(1e-12).is_err());
+    }
+
+    #[test]
+    fn compared_to_bloom_filters() {
+        // For each false positive probability in the Bloom filter tables, a Ribbon filter should
+        // be both smaller and at least as accurate as the best Bloom filter.
+        let n = 100_000;
+        for &p in &[0.1, 0.05, 0.01, 0.005, 0.001] {
+            let (buckets, k) = best_buckets_and_k(p).unwrap();
+            let bloom_fpr = PROBS[buckets][k];
+            let filter = build(n, p);
+
+            let trials = 200_000;
+            let false_positives = (n..n + trials).filter(|i| filter.contains(i)).count();
+            let fpr = false_positives as f64 / trials as f64;
+            assert!(fpr <= bloom_fpr * 1.1, "p={}: {} vs. {}", p, fpr, bloom_fpr);
+            assert!((fpr - filter.false_pos_prob()).abs() < filter.false_pos_prob() * 0.2);
+
+            let bits_per_key = (filter.memory_usage() * 8) as f64 / n as f64;
+            assert!(bits_per_key < buckets as f64);
+            assert!(bits_per_key < filter.fingerprint_bits() as f64 * 1.15);
+            assert!(filter.memory_usage() < BloomFilter::<u64>::new(n as usize, p).memory_usage());
+        }
+    }
+
+    #[test]
+    fn to_and_from_bytes() {
+        let filter = build(1000, 0.01);
+        let decoded = RibbonFilter::<u64>::from_bytes(&filter.to_bytes()).unwrap();
+        assert_eq!(decoded.rows, filter.rows);
+        assert_eq!(decoded.len(), 1000);
+        assert!((0..1000).all(|i| decoded.contains(&i)));
+
+        let empty = build(0, 0.01);
+        let decoded = RibbonFilter::<u64>::from_bytes(&empty.to_bytes()).unwrap();
+        assert!(decoded.is_empty());
+        assert!(!decoded.contains(&1));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let filter = build(100, 0.01);
+
+        let json = ::serde_json::to_string(&filter).unwrap();
+        let decoded: RibbonFilter<u64> = ::serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.rows, filter.rows);
+        assert!(decoded.contains(&10));
+
+        // More keys than rows, or keys with no rows.
+        let corrupt = json.replacen("\"len\":100", "\"len\":1000", 1);
+        assert_ne!(corrupt, json);
+        assert!(::serde_json::from_str::<RibbonFilter<u64>>(&corrupt).is_err());
+
+        let rows = PackedVec::new(8, 10);
+        let json = format!(
+            r#"{{"seed":1,"len":1,"rows":{},"hasher":{{"k0":0,"k1":0}}}}"#,
+            ::serde_json::to_string(&rows).unwrap()
+        );
+        assert!(::serde_json::from_str::<RibbonFilter<u64>>(&json).is_err());
+    }
+}
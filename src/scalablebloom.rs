@@ -0,0 +1,518 @@
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+
+use bloomfilter::BloomFilter;
+use bloomparams::BloomParams;
+use codec;
+use error::Error;
+#[cfg(feature = "serde")]
+use hash::fingerprint;
+use hash::SipBuildHasher;
+use sketch::{Estimator, Sketch};
+
+/// A scalable Bloom filter is a Bloom filter which grows to accommodate any number of elements
+/// while keeping its probability of false positives under a fixed bound, as described in [Scalable
+/// Bloom Filters](https://doi.org/10.1016/j.ipl.2006.10.007) by Almeida et al.
+///
+/// The filter is a chain of `BloomFilter` slices. Once a slice has had as many elements inserted
+/// as it was sized for, a new slice is added which is `growth` times larger and whose probability
+/// of false positives is `tightening` times smaller. With an initial slice tuned for a probability
+/// of `p * (1 - tightening)`, the overall probability of false positives is bounded by the sum of
+/// the geometric series, `p`.
+///
+/// ```
+/// use sketchy::ScalableBloomFilter;
+///
+/// // Start with room for 1K elements, growing as needed, with a 1% maximum probability of a false
+/// // positive.
+/// let mut filter = ScalableBloomFilter::new(1_000, 0.01);
+///
+/// for i in 0..10_000 {
+///     filter.insert(i);
+/// }
+///
+/// assert!(filter.contains(&5_000));
+/// assert!(filter.slices() > 1);
+/// ```
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        bound(
+            serialize = "S: ::serde::Serialize",
+            deserialize = "E: Hash, S: ::serde::Deserialize<'de> + BuildHasher"
+        ),
+        try_from = "ScalableBloomFilterData<E, S>"
+    )
+)]
+pub struct ScalableBloomFilter<E, S = SipBuildHasher> {
+    initial_capacity: usize,
+    max_false_pos_prob: f64,
+    growth: usize,
+    tightening: f64,
+    count: usize,
+    filters: Vec<BloomFilter<E, S>>,
+    hasher: S,
+}
+
+/// The default factor by which each slice is larger than the last. Almeida et al. recommend 2 for
+/// slow growth and 4 for fast growth.
+const DEFAULT_GROWTH: usize = 2;
+
+/// The default factor by which each slice's probability of false positives is smaller than the
+/// last. Almeida et al. recommend 0.8 to 0.9.
+const DEFAULT_TIGHTENING: f64 = 0.9;
+
+impl<E: Hash> ScalableBloomFilter<E> {
+    /// Creates a new `ScalableBloomFilter` whose first slice is tuned for a population of `n`
+    /// elements, with the given upper bound of the probability of false positives.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero, the probability isn't in the range `(0, 1)`, or the filter would be
+    /// too large.
+    pub fn new(n: usize, max_false_pos_prob: f64) -> ScalableBloomFilter<E> {
+        match ScalableBloomFilter::try_new(n, max_false_pos_prob) {
+            Ok(sbf) => sbf,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Creates a new `ScalableBloomFilter` whose first slice is tuned for a population of `n`
+    /// elements, with the given upper bound of the probability of false positives. Returns an
+    /// error if `n` is zero, the probability isn't in the range `(0, 1)`, or the filter would be
+    /// too large.
+    pub fn try_new(n: usize, max_false_pos_prob: f64) -> Result<ScalableBloomFilter<E>, Error> {
+        ScalableBloomFilter::with_growth(n, max_false_pos_prob, DEFAULT_GROWTH, DEFAULT_TIGHTENING)
+    }
+
+    /// Creates a new `ScalableBloomFilter` whose first slice is tuned for a population of `n`
+    /// elements, with the given upper bound of the probability of false positives, and whose
+    /// slices grow by the given factors. Returns an error if `n` or `growth` is zero, the
+    /// probability or `tightening` isn't in the range `(0, 1)`, or the filter would be too large.
+    pub fn with_growth(
+        n: usize,
+        max_false_pos_prob: f64,
+        growth: usize,
+        tightening: f64,
+    ) -> Result<ScalableBloomFilter<E>, Error> {
+        ScalableBloomFilter::with_growth_and_hasher(
+            n,
+            max_false_pos_prob,
+            growth,
+            tightening,
+            SipBuildHasher::default(),
+        )
+    }
+
+    /// Returns the filter encoded in a compact, versioned binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = codec::header(codec::SCALABLE_BLOOM_FILTER, codec::HASH_SIPHASH24);
+        self.hasher.encode(&mut buf);
+        codec::put_varint(&mut buf, self.initial_capacity as u64);
+        codec::put_f64(&mut buf, self.max_false_pos_prob);
+        codec::put_varint(&mut buf, self.growth as u64);
+        codec::put_f64(&mut buf, self.tightening);
+        codec::put_varint(&mut buf, self.count as u64);
+        codec::put_varint(&mut buf, self.filters.len() as u64);
+        for bf in self.filters.iter() {
+            bf.encode(&mut buf);
+        }
+        codec::finish(buf)
+    }
+
+    /// Decodes a filter from the format produced by `to_bytes`, returning an error if the data is
+    /// corrupt or isn't an encoded `ScalableBloomFilter`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<ScalableBloomFilter<E>, Error> {
+        let mut input = codec::open(bytes, codec::SCALABLE_BLOOM_FILTER, codec::HASH_SIPHASH24)?;
+        let hasher = SipBuildHasher::decode(&mut input)?;
+        let initial_capacity = codec::get_varint(&mut input)? as usize;
+        let max_false_pos_prob = codec::get_f64(&mut input)?;
+        let growth = codec::get_varint(&mut input)? as usize;
+        let tightening = codec::get_f64(&mut input)?;
+        let count = codec::get_varint(&mut input)? as usize;
+        validate(initial_capacity, max_false_pos_prob, growth, tightening)
+            .map_err(|_| Error::InvalidData("invalid parameters"))?;
+
+        let len = codec::get_len(&mut input)?;
+        if len == 0 {
+            return Err(Error::InvalidData("empty filter"));
+        }
+
+        let mut filters = Vec::with_capacity(len);
+        for _ in 0..len {
+            filters.push(BloomFilter::decode(&mut input, hasher)?);
+        }
+        codec::close(input)?;
+
+        Ok(ScalableBloomFilter::<E> {
+            initial_capacity,
+            max_false_pos_prob,
+            growth,
+            tightening,
+            count,
+            filters,
+            hasher,
+        })
+    }
+}
+
+impl<E: Hash, S: BuildHasher + Clone> ScalableBloomFilter<E, S> {
+    /// Creates a new `ScalableBloomFilter` whose first slice is tuned for a population of `n`
+    /// elements, with the given upper bound of the probability of false positives, whose slices
+    /// grow by the given factors, and which hashes elements with the given hasher. Returns an error
+    /// if `n` or `growth` is zero, the probability or `tightening` isn't in the range `(0, 1)`, or
+    /// the filter would be too large.
+    pub fn with_growth_and_hasher(
+        n: usize,
+        max_false_pos_prob: f64,
+        growth: usize,
+        tightening: f64,
+        hasher: S,
+    ) -> Result<ScalableBloomFilter<E, S>, Error> {
+        validate(n, max_false_pos_prob, growth, tightening)?;
+        let first = slice(n, max_false_pos_prob * (1.0 - tightening), hasher.clone())?;
+
+        Ok(ScalableBloomFilter::<E, S> {
+            initial_capacity: n,
+            max_false_pos_prob,
+            growth,
+            tightening,
+            count: 0,
+            filters: vec![first],
+            hasher,
+        })
+    }
+
+    /// Returns the hasher used to hash elements.
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    /// Returns the number of `BloomFilter` slices in the filter.
+    pub fn slices(&self) -> usize {
+        self.filters.len()
+    }
+
+    /// Returns the approximate number of distinct elements in the set. Elements which were false
+    /// positives when inserted aren't counted.
+    pub fn len(&self) -> usize {
+        (0..self.filters.len() - 1)
+            .map(|i| self.capacity(i))
+            .fold(self.count, usize::saturating_add)
+    }
+
+    /// Returns `true` if no elements have been inserted into the set.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds a value to the set, adding a new slice if the current one is full.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the current slice is full and a new slice would be too large.
+    pub fn insert(&mut self, e: E) {
+        if let Err(e) = self.try_insert(e) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Adds a value to the set, adding a new slice if the current one is full, or returns
+    /// `Error::Full` and leaves the filter unmodified if a new slice would be too large.
+    pub fn try_insert(&mut self, e: E) -> Result<(), Error> {
+        if self.contains(&e) {
+            return Ok(());
+        }
+
+        let i = self.filters.len() - 1;
+        if self.count >= self.capacity(i) {
+            let p = self.max_false_pos_prob
+                * (1.0 - self.tightening)
+                * self.tightening.powi((i + 1) as i32);
+            let bf = slice(self.capacity(i + 1), p, self.hasher.clone());
+            self.filters.push(bf.map_err(|_| Error::Full)?);
+            self.count = 0;
+        }
+
+        self.filters.last_mut().unwrap().insert(e);
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Returns `true` if the set probably contains the given element.
+    pub fn contains(&self, e: &E) -> bool {
+        self.filters.iter().any(|bf| bf.contains(e))
+    }
+
+    /// Removes all elements from the set, shrinking it back to a single slice.
+    pub fn clear(&mut self) {
+        self.filters.truncate(1);
+        self.filters[0].clear();
+        self.count = 0;
+    }
+
+    /// Returns the approximate amount of memory used by the filter, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>()
+            + (self.filters.capacity() - self.filters.len()) * mem::size_of::<BloomFilter<E, S>>()
+            + self
+                .filters
+                .iter()
+                .map(|bf| bf.memory_usage())
+                .sum::<usize>()
+    }
+
+    /// Returns the number of elements the `i`th slice is sized for.
+    fn capacity(&self, i: usize) -> usize {
+        (0..i).fold(self.initial_capacity, |n, _| n.saturating_mul(self.growth))
+    }
+}
+
+impl<E: Hash, S: BuildHasher + Clone> Sketch<E> for ScalableBloomFilter<E, S> {
+    fn insert(&mut self, e: E) {
+        ScalableBloomFilter::insert(self, e)
+    }
+
+    fn clear(&mut self) {
+        ScalableBloomFilter::clear(self)
+    }
+
+    fn memory_usage(&self) -> usize {
+        ScalableBloomFilter::memory_usage(self)
+    }
+}
+
+impl<E: Hash, S: BuildHasher + Clone> Estimator<E> for ScalableBloomFilter<E, S> {
+    type Estimate = bool;
+
+    fn estimate(&self, e: &E) -> bool {
+        self.contains(e)
+    }
+}
+
+/// Returns a new, empty slice of `n` elements with the given probability of false positives.
+/// Slices' probabilities quickly fall below the range of `BloomFilter::new`'s tables, so this uses
+/// the closed-form planner.
+fn slice<E: Hash, S: BuildHasher>(n: usize, p: f64, hasher: S) -> Result<BloomFilter<E, S>, Error> {
+    let params = BloomParams::optimal(n, p)?;
+    BloomFilter::with_params_and_hasher(params.bits(), params.k(), hasher)
+}
+
+fn validate(
+    n: usize,
+    max_false_pos_prob: f64,
+    growth: usize,
+    tightening: f64,
+) -> Result<(), Error> {
+    if n == 0 {
+        return Err(Error::InvalidParameter("initial capacity must be positive"));
+    }
+
+    if !(max_false_pos_prob > 0.0 && max_false_pos_prob < 1.0) {
+        return Err(Error::InvalidErrorRate(max_false_pos_prob));
+    }
+
+    if growth == 0 {
+        return Err(Error::InvalidParameter("growth must be positive"));
+    }
+
+    if !(tightening > 0.0 && tightening < 1.0) {
+        return Err(Error::InvalidParameter("tightening must be in (0, 1)"));
+    }
+    Ok(())
+}
+
+/// The fields of a deserialized `ScalableBloomFilter`, which are checked before use.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "S: ::serde::Deserialize<'de>"))]
+struct ScalableBloomFilterData<E, S> {
+    initial_capacity: usize,
+    max_false_pos_prob: f64,
+    growth: usize,
+    tightening: f64,
+    count: usize,
+    filters: Vec<BloomFilter<E, S>>,
+    hasher: S,
+}
+
+#[cfg(feature = "serde")]
+impl<E: Hash, S: BuildHasher> ::std::convert::TryFrom<ScalableBloomFilterData<E, S>>
+    for ScalableBloomFilter<E, S>
+{
+    type Error = Error;
+
+    fn try_from(data: ScalableBloomFilterData<E, S>) -> Result<ScalableBloomFilter<E, S>, Error> {
+        validate(
+            data.initial_capacity,
+            data.max_false_pos_prob,
+            data.growth,
+            data.tightening,
+        )
+        .map_err(|_| Error::InvalidData("invalid parameters"))?;
+
+        if data.filters.is_empty() {
+            return Err(Error::InvalidData("empty filter"));
+        }
+
+        let hasher = fingerprint(&data.hasher);
+        if data
+            .filters
+            .iter()
+            .any(|bf| fingerprint(bf.hasher()) != hasher)
+        {
+            return Err(Error::InvalidData("inconsistent hashers"));
+        }
+
+        Ok(ScalableBloomFilter {
+            initial_capacity: data.initial_capacity,
+            max_false_pos_prob: data.max_false_pos_prob,
+            growth: data.growth,
+            tightening: data.tightening,
+            count: data.count,
+            filters: data.filters,
+            hasher: data.hasher,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_and_query() {
+        let mut sbf = ScalableBloomFilter::new(100, 0.01);
+        assert!(sbf.is_empty());
+
+        sbf.insert(100);
+        sbf.insert(400);
+        sbf.insert(400);
+
+        assert!(sbf.contains(&100));
+        assert!(sbf.contains(&400));
+        assert_eq!(sbf.len(), 2);
+        assert_eq!(sbf.slices(), 1);
+    }
+
+    #[test]
+    fn growth() {
+        let mut sbf = ScalableBloomFilter::new(100, 0.01);
+        for i in 0..1000 {
+            sbf.insert(i);
+        }
+
+        // Slices of 100, 200, 400 and 800 elements.
+        assert_eq!(sbf.slices(), 4);
+        assert!(sbf.len() <= 1000 && sbf.len() > 990);
+        assert!((0..1000).all(|i| sbf.contains(&i)));
+
+        sbf.clear();
+        assert_eq!(sbf.slices(), 1);
+        assert!(sbf.is_empty());
+        assert!(!sbf.contains(&10));
+    }
+
+    #[test]
+    fn false_positive_bound() {
+        let p = 0.01;
+        let mut sbf = ScalableBloomFilter::new(1000, p);
+        for i in 0..50_000u64 {
+            sbf.insert(i);
+        }
+
+        let trials = 100_000u64;
+        let false_positives = (50_000..50_000 + trials)
+            .filter(|i| sbf.contains(i))
+            .count();
+        assert!(
+            (false_positives as f64) < trials as f64 * p,
+            "{} false positives",
+            false_positives
+        );
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        assert!(ScalableBloomFilter::<u64>::try_new(0, 0.01).is_err());
+        assert!(ScalableBloomFilter::<u64>::try_new(100, 0.0).is_err());
+        assert!(ScalableBloomFilter::<u64>::with_growth(100, 0.01, 0, 0.9).is_err());
+        assert!(ScalableBloomFilter::<u64>::with_growth(100, 0.01, 2, 1.0).is_err());
+        assert!(ScalableBloomFilter::<u64>::with_growth(100, 0.01, 4, 0.8).is_ok());
+    }
+
+    #[test]
+    fn too_large() {
+        // The second slice would have room for `usize::MAX` elements.
+        let mut sbf = ScalableBloomFilter::with_growth(1, 0.01, usize::MAX, 0.9).unwrap();
+        assert_eq!(sbf.try_insert(1), Ok(()));
+        assert_eq!(sbf.try_insert(1), Ok(()));
+        assert_eq!(sbf.try_insert(2), Err(Error::Full));
+        assert_eq!(sbf.slices(), 1);
+        assert_eq!(sbf.len(), 1);
+    }
+
+    #[test]
+    fn to_and_from_bytes() {
+        let mut sbf = ScalableBloomFilter::new(100, 0.01);
+        for i in 0..500 {
+            sbf.insert(i);
+        }
+
+        let bytes = sbf.to_bytes();
+        let mut decoded = ScalableBloomFilter::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.slices(), sbf.slices());
+        assert_eq!(decoded.len(), sbf.len());
+        assert!((0..500).all(|i| decoded.contains(&i)));
+
+        // The decoded filter keeps growing from where the original left off.
+        for i in 500..1000 {
+            decoded.insert(i);
+        }
+        assert_eq!(decoded.slices(), 4);
+
+        assert_eq!(
+            ScalableBloomFilter::<u64>::from_bytes(&BloomFilter::<u64>::new(10, 0.1).to_bytes())
+                .err(),
+            Some(Error::UnexpectedType(codec::BLOOM_FILTER))
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let mut sbf = ScalableBloomFilter::new(100, 0.01);
+        for i in 0..500 {
+            sbf.insert(i);
+        }
+
+        let json = ::serde_json::to_string(&sbf).unwrap();
+        let decoded: ScalableBloomFilter<i32> = ::serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.slices(), sbf.slices());
+        assert!((0..500).all(|i| decoded.contains(&i)));
+
+        let json = ::serde_json::to_string(&ScalableBloomFilter::<i32>::new(100, 0.01)).unwrap();
+        let other = ScalableBloomFilter::<i32>::with_growth_and_hasher(
+            100,
+            0.01,
+            2,
+            0.9,
+            SipBuildHasher::new(1, 2),
+        )
+        .unwrap();
+        let other = ::serde_json::to_string(&other.filters).unwrap();
+        let start = json.find("\"filters\":").unwrap() + "\"filters\":".len();
+        let end = json.find(",\"hasher\"").unwrap();
+        for &(from, to) in &[
+            ("\"growth\":2", "\"growth\":0"),
+            ("\"tightening\":0.9", "\"tightening\":1.5"),
+            (&json[start..end], "[]"),
+            (&json[start..end], &other),
+        ] {
+            let corrupt = json.replacen(from, to, 1);
+            assert_ne!(corrupt, json);
+            assert!(::serde_json::from_str::<ScalableBloomFilter<i32>>(&corrupt).is_err());
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Traits shared by the sketches in this crate, which allow code to be generic over the kind of
+//! sketch it builds, combines, or queries.
+
+use error::Error;
+
+/// A probabilistic summary of a stream of elements.
+///
+/// ```
+/// use sketchy::{HyperLogLog, Sketch};
+///
+/// fn summarize<S: Sketch<u64>>(sketch: &mut S, values: &[u64]) {
+///     for &v in values {
+///         sketch.insert(v);
+///     }
+/// }
+///
+/// let mut hll = HyperLogLog::new(0.05);
+/// summarize(&mut hll, &[1, 2, 3]);
+///
+/// assert!(hll.cardinality() > 2.5);
+/// ```
+pub trait Sketch<E> {
+    /// Adds an element to the sketch.
+    fn insert(&mut self, e: E);
+
+    /// Resets the sketch to its empty state, keeping its parameters.
+    fn clear(&mut self);
+
+    /// Returns the approximate amount of memory used by the sketch, in bytes.
+    fn memory_usage(&self) -> usize;
+}
+
+/// A sketch which can absorb the contents of another sketch of the same type, such that the result
+/// summarizes both streams.
+///
+/// ```
+/// use sketchy::{CountMinSketch, Mergeable};
+///
+/// let mut one = CountMinSketch::new(4, 100);
+/// one.insert("a");
+///
+/// let mut two = CountMinSketch::new(4, 100);
+/// two.insert("a");
+///
+/// Mergeable::merge(&mut one, &two).unwrap();
+/// assert_eq!(one.estimate(&"a"), 2);
+///
+/// let other = CountMinSketch::new(4, 200);
+/// assert!(one.check_compatible(&other).is_err());
+/// ```
+pub trait Mergeable {
+    /// Returns an error if `other` can't be merged into `self`, e.g. because the sketches have
+    /// different dimensions or hashers.
+    fn check_compatible(&self, other: &Self) -> Result<(), Error>;
+
+    /// Merges the contents of `other` into `self`. Returns an error and leaves `self` unmodified if
+    /// the sketches are incompatible.
+    fn merge(&mut self, other: &Self) -> Result<(), Error>;
+}
+
+/// A sketch which can answer approximate queries about individual elements, such as whether an
+/// element is a member of a set or how often it occurred.
+pub trait Estimator<E> {
+    /// The type of the answer to a query.
+    type Estimate;
+
+    /// Returns an estimate for the given element.
+    fn estimate(&self, e: &E) -> Self::Estimate;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bloomfilter::BloomFilter;
+    use countmin::CountMinSketch;
+    use hash::SipBuildHasher;
+    use hyperloglog::HyperLogLog;
+    use reservoir::ReservoirSample;
+    use topk::TopK;
+
+    fn fill<S: Sketch<u64>>(sketch: &mut S, values: ::std::ops::Range<u64>) {
+        for v in values {
+            sketch.insert(v);
+        }
+    }
+
+    fn combine<S: Sketch<u64> + Mergeable>(mut a: S, b: S) -> Result<S, Error> {
+        a.check_compatible(&b)?;
+        a.merge(&b)?;
+        Ok(a)
+    }
+
+    #[test]
+    fn generic_insert_and_clear() {
+        let mut bf = BloomFilter::new(100, 0.01);
+        fill(&mut bf, 0..10);
+        assert!(bf.estimate(&5));
+        Sketch::clear(&mut bf);
+        assert!(!bf.estimate(&5));
+
+        let mut cms = CountMinSketch::new(4, 100);
+        fill(&mut cms, 0..10);
+        assert_eq!(Estimator::estimate(&cms, &5), 1);
+        Sketch::clear(&mut cms);
+        assert_eq!(Estimator::estimate(&cms, &5), 0);
+
+        let mut hll = HyperLogLog::new(0.05);
+        fill(&mut hll, 0..10);
+        assert!(hll.cardinality() > 9.0);
+        Sketch::clear(&mut hll);
+        assert_eq!(hll.cardinality(), 0.0);
+
+        let mut sample = ReservoirSample::new(5);
+        fill(&mut sample, 0..10);
+        Sketch::clear(&mut sample);
+        assert!(sample.elements().is_empty());
+    }
+
+    #[test]
+    fn generic_merge() {
+        let mut a = BloomFilter::new(100, 0.01);
+        let mut b = BloomFilter::new(100, 0.01);
+        fill(&mut a, 0..10);
+        fill(&mut b, 10..20);
+        let bf = combine(a, b).unwrap();
+        assert!((0..20).all(|v| bf.contains(&v)));
+
+        let mut a = HyperLogLog::new(0.05);
+        let mut b = HyperLogLog::new(0.05);
+        fill(&mut a, 0..1000);
+        fill(&mut b, 500..1500);
+        let hll = combine(a, b).unwrap();
+        assert!((hll.cardinality() - 1500.0).abs() < 1500.0 * 0.1);
+
+        let mut a = TopK::new(2, 0.2, CountMinSketch::new(4, 100));
+        let mut b = TopK::new(2, 0.2, CountMinSketch::new(4, 100));
+        fill(&mut a, 0..2);
+        a.insert(7);
+        fill(&mut b, 3..6);
+        b.insert(7);
+        let mut topk = combine(a, b).unwrap();
+        assert_eq!(topk.estimate(&7), 2);
+        assert_eq!(topk.elements(), vec![7]);
+    }
+
+    #[test]
+    fn incompatible_merges() {
+        let bf = BloomFilter::<u64>::new(100, 0.01);
+        assert_eq!(
+            bf.check_compatible(&BloomFilter::new(200, 0.01)),
+            Err(Error::Incompatible("different parameters"))
+        );
+        assert_eq!(
+            combine(
+                bf,
+                BloomFilter::with_hasher(100, 0.01, SipBuildHasher::new(1, 2))
+            )
+            .err(),
+            Some(Error::HasherMismatch)
+        );
+
+        let mut cms = CountMinSketch::<u64>::new(4, 100);
+        cms.insert(1);
+        assert_eq!(
+            Mergeable::merge(&mut cms, &CountMinSketch::new(4, 200)),
+            Err(Error::Incompatible("different dimensions"))
+        );
+        assert_eq!(cms.estimate(&1), 1);
+
+        let mut hll = HyperLogLog::<u64>::new(0.05);
+        let other = HyperLogLog::with_precision_and_hasher(9, SipBuildHasher::new(1, 2)).unwrap();
+        assert_eq!(
+            Mergeable::merge(&mut hll, &other),
+            Err(Error::HasherMismatch)
+        );
+    }
+}
@@ -1,6 +1,12 @@
+use codec::{self, Codec};
 use countmin::CountMinSketch;
+use error::Error;
+use hash::SipBuildHasher;
+use sketch::{Estimator, Mergeable, Sketch};
+use std::cmp::Reverse;
 use std::collections::HashSet;
-use std::hash::Hash;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
 
 /// A Top-K heap is a probabilistic data structure which uses a Count-Min Sketch to calculate the
 /// top K elements in a data stream with the highest frequency.
@@ -18,25 +24,50 @@ use std::hash::Hash;
 ///
 /// assert_eq!(topk.elements(), vec![-100]);
 /// ```
-pub struct TopK<E> {
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        bound(
+            serialize = "E: ::serde::Serialize, S: ::serde::Serialize",
+            deserialize = "E: ::serde::Deserialize<'de> + Eq + Hash, S: ::serde::Deserialize<'de>"
+        ),
+        try_from = "TopKData<E, S>"
+    )
+)]
+pub struct TopK<E, S = SipBuildHasher> {
     k: usize,
     min: f64,
     n: u64,
-    cms: CountMinSketch<E>,
+    cms: CountMinSketch<E, S>,
     elements: HashSet<E>,
 }
 
-impl<E: Eq + Hash + Copy> TopK<E> {
+impl<E: Eq + Hash + Copy, S: BuildHasher> TopK<E, S> {
     /// Returns a TopK which will track `k` elements with at least `min` frequency (`(0,1)`) using
     /// the given CountMinSketch.
-    pub fn new(k: usize, min: f64, cms: CountMinSketch<E>) -> TopK<E> {
-        TopK::<E> {
+    ///
+    /// # Panics
+    ///
+    /// Panics if `k` is zero or `min` isn't in the range `(0, 1)`.
+    pub fn new(k: usize, min: f64, cms: CountMinSketch<E, S>) -> TopK<E, S> {
+        match TopK::try_new(k, min, cms) {
+            Ok(topk) => topk,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Returns a TopK which will track `k` elements with at least `min` frequency using the given
+    /// CountMinSketch, or an error if `k` is zero or `min` isn't in the range `(0, 1)`.
+    pub fn try_new(k: usize, min: f64, cms: CountMinSketch<E, S>) -> Result<TopK<E, S>, Error> {
+        validate(k, min)?;
+        Ok(TopK::<E, S> {
             k,
             min,
             n: 0,
             cms,
             elements: HashSet::with_capacity(k),
-        }
+        })
     }
 
     /// Adds a value to the heap.
@@ -56,7 +87,7 @@ impl<E: Eq + Hash + Copy> TopK<E> {
             .filter(|e| self.is_top(e))
             .cloned()
             .collect();
-        v.sort_by(|a, b| self.cms.estimate(b).cmp(&self.cms.estimate(a)));
+        v.sort_by_key(|e| Reverse(self.cms.estimate(e)));
         v.into_iter().take(self.k).collect()
     }
 
@@ -68,12 +99,156 @@ impl<E: Eq + Hash + Copy> TopK<E> {
         }
     }
 
+    /// Removes all elements from the heap and resets its CountMinSketch.
+    pub fn clear(&mut self) {
+        self.n = 0;
+        self.cms.clear();
+        self.elements.clear();
+    }
+
+    /// Returns the approximate amount of memory used by the heap, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>() - mem::size_of::<CountMinSketch<E, S>>()
+            + self.cms.memory_usage()
+            + self.elements.capacity() * mem::size_of::<E>()
+    }
+
     fn is_top(&self, e: &E) -> bool {
         let freq = self.cms.estimate(e) as f64 / self.n as f64;
         freq > self.min
     }
 }
 
+impl<E: Eq + Hash + Copy, S: BuildHasher> Sketch<E> for TopK<E, S> {
+    fn insert(&mut self, e: E) {
+        TopK::insert(self, e)
+    }
+
+    fn clear(&mut self) {
+        TopK::clear(self)
+    }
+
+    fn memory_usage(&self) -> usize {
+        TopK::memory_usage(self)
+    }
+}
+
+/// Merging sums the frequencies in the heaps' CountMinSketches and keeps the candidates from both
+/// heaps which are still in the top K of the combined stream.
+impl<E: Eq + Hash + Copy, S: BuildHasher> Mergeable for TopK<E, S> {
+    fn check_compatible(&self, other: &Self) -> Result<(), Error> {
+        self.cms.check_compatible(&other.cms)
+    }
+
+    fn merge(&mut self, other: &Self) -> Result<(), Error> {
+        Mergeable::merge(&mut self.cms, &other.cms)?;
+        self.n += other.n;
+        self.elements.extend(other.elements.iter().cloned());
+        self.elements = self
+            .elements
+            .iter()
+            .filter(|e| self.is_top(e))
+            .cloned()
+            .collect();
+        self.shrink_to_fit();
+        Ok(())
+    }
+}
+
+impl<E: Eq + Hash + Copy, S: BuildHasher> Estimator<E> for TopK<E, S> {
+    type Estimate = u64;
+
+    /// Returns the estimated frequency of the given element.
+    fn estimate(&self, e: &E) -> u64 {
+        self.cms.estimate(e)
+    }
+}
+
+impl<E: Eq + Hash + Copy + Codec> TopK<E> {
+    /// Returns the heap encoded in a compact, versioned binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = codec::header(codec::TOP_K, codec::HASH_SIPHASH24);
+        codec::put_varint(&mut buf, self.k as u64);
+        codec::put_f64(&mut buf, self.min);
+        codec::put_varint(&mut buf, self.n);
+        self.cms.encode(&mut buf);
+        codec::put_varint(&mut buf, self.elements.len() as u64);
+        for e in self.elements.iter() {
+            e.encode(&mut buf);
+        }
+        codec::finish(buf)
+    }
+
+    /// Decodes a heap from the format produced by `to_bytes`, returning an error if the data is
+    /// corrupt or isn't an encoded `TopK`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<TopK<E>, Error> {
+        let mut input = codec::open(bytes, codec::TOP_K, codec::HASH_SIPHASH24)?;
+        let k = codec::get_varint(&mut input)? as usize;
+        let min = codec::get_f64(&mut input)?;
+        let n = codec::get_varint(&mut input)?;
+        let cms = CountMinSketch::decode(&mut input)?;
+        validate(k, min).map_err(|_| Error::InvalidData("invalid parameters"))?;
+
+        let len = codec::get_len(&mut input)?;
+        let mut elements = HashSet::with_capacity(len);
+        for _ in 0..len {
+            elements.insert(E::decode(&mut input)?);
+        }
+        codec::close(input)?;
+
+        Ok(TopK::<E> {
+            k,
+            min,
+            n,
+            cms,
+            elements,
+        })
+    }
+}
+
+fn validate(k: usize, min: f64) -> Result<(), Error> {
+    if k == 0 {
+        return Err(Error::InvalidParameter("k must be positive"));
+    }
+
+    if !(min > 0.0 && min < 1.0) {
+        return Err(Error::InvalidParameter(
+            "minimum frequency must be in (0, 1)",
+        ));
+    }
+    Ok(())
+}
+
+/// The fields of a deserialized `TopK`, which are checked before use.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(bound(
+    deserialize = "E: ::serde::Deserialize<'de> + Eq + Hash, S: ::serde::Deserialize<'de>"
+))]
+struct TopKData<E, S> {
+    k: usize,
+    min: f64,
+    n: u64,
+    cms: CountMinSketch<E, S>,
+    elements: HashSet<E>,
+}
+
+#[cfg(feature = "serde")]
+impl<E, S> ::std::convert::TryFrom<TopKData<E, S>> for TopK<E, S> {
+    type Error = Error;
+
+    fn try_from(data: TopKData<E, S>) -> Result<TopK<E, S>, Error> {
+        validate(data.k, data.min).map_err(|_| Error::InvalidData("invalid parameters"))?;
+        Ok(TopK::<E, S> {
+            k: data.k,
+            min: data.min,
+            n: data.n,
+            cms: data.cms,
+            elements: data.elements,
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -91,4 +266,61 @@ mod test {
 
         assert_eq!(topk.elements(), vec![-100]);
     }
+
+    #[test]
+    fn invalid_parameters() {
+        let cms = || CountMinSketch::<u8>::new(4, 100);
+        assert!(TopK::try_new(0, 0.05, cms()).is_err());
+        assert!(TopK::try_new(5, 0.0, cms()).is_err());
+        assert!(TopK::try_new(5, 1.0, cms()).is_err());
+        assert!(TopK::try_new(5, 0.05, cms()).is_ok());
+    }
+
+    #[test]
+    fn to_and_from_bytes() {
+        let cms = CountMinSketch::with_confidence(0.001, 0.99);
+        let mut topk = TopK::new(5, 0.05, cms);
+
+        for i in 1..1000 {
+            topk.insert(i % 100);
+            topk.insert(-100);
+        }
+
+        let mut decoded = TopK::<i32>::from_bytes(&topk.to_bytes()).unwrap();
+        assert_eq!(decoded.n, topk.n);
+        assert_eq!(decoded.elements, topk.elements);
+        assert_eq!(decoded.elements(), vec![-100]);
+
+        for &(k, min) in &[(0, 0.05), (5, 0.0), (5, 1.5), (5, f64::NAN)] {
+            let mut invalid = TopK::<i32>::new(5, 0.05, CountMinSketch::new(4, 100));
+            invalid.k = k;
+            invalid.min = min;
+            assert_eq!(
+                TopK::<i32>::from_bytes(&invalid.to_bytes()).err(),
+                Some(Error::InvalidData("invalid parameters"))
+            );
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let cms = CountMinSketch::with_confidence(0.001, 0.99);
+        let mut topk = TopK::new(5, 0.05, cms);
+
+        for i in 1..1000 {
+            topk.insert(i % 100);
+            topk.insert(-100);
+        }
+
+        let json = ::serde_json::to_string(&topk).unwrap();
+        let mut decoded: TopK<i32> = ::serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.elements, topk.elements);
+        assert_eq!(decoded.elements(), vec![-100]);
+
+        for &(old, new) in &[(r#""k":5"#, r#""k":0"#), (r#""min":0.05"#, r#""min":2.0"#)] {
+            let corrupt = json.replacen(old, new, 1);
+            assert!(::serde_json::from_str::<TopK<i32>>(&corrupt).is_err());
+        }
+    }
 }
@@ -0,0 +1,692 @@
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::BitXor;
+
+use codec;
+use error::Error;
+use hash::{mix, splitmix64, SipBuildHasher};
+use sketch::Estimator;
+
+/// An xor filter is an immutable probabilistic set which is built once from a known set of keys,
+/// as described in [Xor Filters: Faster and Smaller Than Bloom and Cuckoo
+/// Filters](https://arxiv.org/abs/1912.08258) by Graf and Lemire. It stores an 8-bit fingerprint
+/// in each of about `1.23 * n` slots, and has a false positive probability of about 1/256.
+///
+/// ```
+/// use sketchy::XorFilter8;
+///
+/// let filter = XorFilter8::new(&["one", "two", "three"]);
+///
+/// assert!(filter.contains(&"one"));
+/// assert_eq!(filter.len(), 3);
+/// ```
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        bound(
+            serialize = "S: ::serde::Serialize",
+            deserialize = "S: ::serde::Deserialize<'de>"
+        ),
+        try_from = "StaticFilterData<u8, S>"
+    )
+)]
+pub struct XorFilter8<E, S = SipBuildHasher> {
+    table: Table<u8>,
+    hasher: S,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    marker: PhantomData<E>,
+}
+
+/// A binary fuse filter is an immutable probabilistic set which is built once from a known set of
+/// keys, as described in [Binary Fuse Filters: Fast and Smaller Than Xor
+/// Filters](https://arxiv.org/abs/2201.01174) by Graf and Lemire. It stores an 8-bit fingerprint in
+/// each of about `1.13 * n` slots for large sets, and has a false positive probability of about
+/// 1/256.
+///
+/// ```
+/// use sketchy::BinaryFuseFilter8;
+///
+/// let filter = BinaryFuseFilter8::new(&["one", "two", "three"]);
+///
+/// assert!(filter.contains(&"one"));
+/// ```
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        bound(
+            serialize = "S: ::serde::Serialize",
+            deserialize = "S: ::serde::Deserialize<'de>"
+        ),
+        try_from = "StaticFilterData<u8, S>"
+    )
+)]
+pub struct BinaryFuseFilter8<E, S = SipBuildHasher> {
+    table: Table<u8>,
+    hasher: S,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    marker: PhantomData<E>,
+}
+
+/// A binary fuse filter with 16-bit fingerprints, which has a false positive probability of about
+/// 1/65536 at twice the size of a `BinaryFuseFilter8`.
+///
+/// ```
+/// use sketchy::BinaryFuseFilter16;
+///
+/// let filter = BinaryFuseFilter16::new(&["one", "two", "three"]);
+///
+/// assert!(filter.contains(&"one"));
+/// ```
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        bound(
+            serialize = "S: ::serde::Serialize",
+            deserialize = "S: ::serde::Deserialize<'de>"
+        ),
+        try_from = "StaticFilterData<u16, S>"
+    )
+)]
+pub struct BinaryFuseFilter16<E, S = SipBuildHasher> {
+    table: Table<u16>,
+    hasher: S,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    marker: PhantomData<E>,
+}
+
+macro_rules! static_filter {
+    ($name:ident, $fp:ty, $tag:expr, $fuse:expr) => {
+        impl<E: Hash> $name<E> {
+            /// Creates a new filter containing the given keys.
+            ///
+            /// # Panics
+            ///
+            /// Panics if the keys contain duplicates.
+            pub fn new(keys: &[E]) -> $name<E> {
+                match $name::try_new(keys) {
+                    Ok(f) => f,
+                    Err(e) => panic!("{}", e),
+                }
+            }
+
+            /// Creates a new filter containing the given keys, or returns an error if the keys
+            /// contain duplicates.
+            pub fn try_new(keys: &[E]) -> Result<$name<E>, Error> {
+                $name::try_with_hasher(keys, SipBuildHasher::default())
+            }
+
+            /// Returns the filter encoded in a compact, versioned binary format.
+            pub fn to_bytes(&self) -> Vec<u8> {
+                let mut buf = codec::header($tag, codec::HASH_SIPHASH24);
+                self.hasher.encode(&mut buf);
+                self.table.encode(&mut buf);
+                codec::finish(buf)
+            }
+
+            /// Decodes a filter from the format produced by `to_bytes`, returning an error if the
+            /// data is corrupt or is an encoding of a different type.
+            pub fn from_bytes(bytes: &[u8]) -> Result<$name<E>, Error> {
+                let mut input = codec::open(bytes, $tag, codec::HASH_SIPHASH24)?;
+                let hasher = SipBuildHasher::decode(&mut input)?;
+                let table = Table::decode(&mut input, $fuse)?;
+                codec::close(input)?;
+
+                Ok($name::<E> {
+                    table,
+                    hasher,
+                    marker: PhantomData,
+                })
+            }
+        }
+
+        impl<E: Hash, S: BuildHasher> $name<E, S> {
+            /// Creates a new filter containing the given keys, which hashes them with the given
+            /// hasher.
+            ///
+            /// # Panics
+            ///
+            /// Panics if the keys contain duplicates.
+            pub fn with_hasher(keys: &[E], hasher: S) -> $name<E, S> {
+                match $name::try_with_hasher(keys, hasher) {
+                    Ok(f) => f,
+                    Err(e) => panic!("{}", e),
+                }
+            }
+
+            /// Creates a new filter containing the given keys, which hashes them with the given
+            /// hasher. Returns an error if the keys contain duplicates.
+            pub fn try_with_hasher(keys: &[E], hasher: S) -> Result<$name<E, S>, Error> {
+                let hashes = keys.iter().map(|k| hasher.hash_one(k)).collect();
+                Ok($name::<E, S> {
+                    table: Table::build(hashes, $fuse)?,
+                    hasher,
+                    marker: PhantomData,
+                })
+            }
+
+            /// Returns the hasher used to hash keys.
+            pub fn hasher(&self) -> &S {
+                &self.hasher
+            }
+
+            /// Returns the number of keys in the filter.
+            pub fn len(&self) -> usize {
+                self.table.len
+            }
+
+            /// Returns `true` if the filter contains no keys.
+            pub fn is_empty(&self) -> bool {
+                self.table.len == 0
+            }
+
+            /// Returns `true` if the set probably contains the given element.
+            pub fn contains(&self, e: &E) -> bool {
+                self.table.contains(self.hasher.hash_one(e))
+            }
+
+            /// Returns the approximate amount of memory used by the filter, in bytes.
+            pub fn memory_usage(&self) -> usize {
+                mem::size_of::<Self>() + self.table.memory_usage()
+            }
+        }
+
+        impl<E: Hash, S: BuildHasher> Estimator<E> for $name<E, S> {
+            type Estimate = bool;
+
+            fn estimate(&self, e: &E) -> bool {
+                self.contains(e)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<E, S> ::std::convert::TryFrom<StaticFilterData<$fp, S>> for $name<E, S> {
+            type Error = Error;
+
+            fn try_from(data: StaticFilterData<$fp, S>) -> Result<$name<E, S>, Error> {
+                data.table.layout.check($fuse)?;
+                data.table.check()?;
+                Ok($name {
+                    table: data.table,
+                    hasher: data.hasher,
+                    marker: PhantomData,
+                })
+            }
+        }
+    };
+}
+
+static_filter!(XorFilter8, u8, codec::XOR_FILTER8, false);
+static_filter!(BinaryFuseFilter8, u8, codec::BINARY_FUSE_FILTER8, true);
+static_filter!(BinaryFuseFilter16, u16, codec::BINARY_FUSE_FILTER16, true);
+
+/// The fields of a deserialized static filter, which are checked before use.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct StaticFilterData<F, S> {
+    table: Table<F>,
+    hasher: S,
+}
+
+/// A type of fingerprint stored in a filter's slots.
+trait Fingerprint: Copy + Default + PartialEq + BitXor<Output = Self> {
+    /// The size of the fingerprint, in bytes.
+    const BYTES: usize;
+
+    /// Returns the fingerprint of a hash.
+    fn from_hash(h: u64) -> Self;
+
+    /// Appends the fingerprint to the buffer.
+    fn put(self, buf: &mut Vec<u8>);
+
+    /// Returns the fingerprint encoded in the given bytes.
+    fn get(bytes: &[u8]) -> Self;
+}
+
+impl Fingerprint for u8 {
+    const BYTES: usize = 1;
+
+    fn from_hash(h: u64) -> u8 {
+        (h ^ (h >> 32)) as u8
+    }
+
+    fn put(self, buf: &mut Vec<u8>) {
+        buf.push(self);
+    }
+
+    fn get(bytes: &[u8]) -> u8 {
+        bytes[0]
+    }
+}
+
+impl Fingerprint for u16 {
+    const BYTES: usize = 2;
+
+    fn from_hash(h: u64) -> u16 {
+        (h ^ (h >> 32)) as u16
+    }
+
+    fn put(self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn get(bytes: &[u8]) -> u16 {
+        u16::from_le_bytes([bytes[0], bytes[1]])
+    }
+}
+
+/// The number of seeds tried before construction fails.
+const MAX_ATTEMPTS: usize = 100;
+
+/// The largest segment of a binary fuse filter.
+const MAX_SEGMENT_LENGTH: usize = 1 << 18;
+
+/// The slots of an xor or binary fuse filter, such that the fingerprint of each key is the xor of
+/// its three slots.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Table<F> {
+    seed: u64,
+    len: usize,
+    layout: Layout,
+    fingerprints: Vec<F>,
+}
+
+/// How a key's hash is mapped to its three slots.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+enum Layout {
+    /// An xor filter's slots are split into three blocks, with one slot per key in each.
+    Xor { block_length: usize },
+    /// A binary fuse filter's slots are split into many small segments, and each key's slots are
+    /// in three consecutive segments.
+    BinaryFuse {
+        segment_length: usize,
+        segment_count_length: usize,
+    },
+}
+
+impl<F: Fingerprint> Table<F> {
+    /// Builds a table for the given key hashes by repeatedly peeling off slots which only one key
+    /// maps to, then assigning fingerprints in reverse order.
+    fn build(mut hashes: Vec<u64>, fuse: bool) -> Result<Table<F>, Error> {
+        if hashes.len() > u32::MAX as usize {
+            return Err(Error::InvalidParameter("too many keys"));
+        }
+
+        // Keys with the same hash always map to the same slots, so they can never be peeled.
+        hashes.sort_unstable();
+        if hashes.windows(2).any(|w| w[0] == w[1]) {
+            return Err(Error::InvalidParameter("duplicate keys"));
+        }
+
+        let layout = Layout::new(hashes.len(), fuse);
+        let size = layout.size();
+        let mut counts = vec![0u32; size];
+        let mut xors = vec![0u64; size];
+        let mut queue = Vec::new();
+        let mut stack = Vec::with_capacity(hashes.len());
+        let mut seed = 0;
+        for _ in 0..MAX_ATTEMPTS {
+            seed = splitmix64(seed);
+            counts.iter_mut().for_each(|c| *c = 0);
+            xors.iter_mut().for_each(|x| *x = 0);
+            stack.clear();
+
+            for &h in hashes.iter() {
+                let h = mix(h, seed);
+                for &p in layout.slots(h).iter() {
+                    counts[p] += 1;
+                    xors[p] ^= h;
+                }
+            }
+
+            queue.extend((0..size).filter(|&p| counts[p] == 1));
+            while let Some(p) = queue.pop() {
+                if counts[p] != 1 {
+                    continue;
+                }
+
+                let h = xors[p];
+                stack.push((h, p));
+                for &q in layout.slots(h).iter() {
+                    counts[q] -= 1;
+                    xors[q] ^= h;
+                    if counts[q] == 1 {
+                        queue.push(q);
+                    }
+                }
+            }
+
+            if stack.len() == hashes.len() {
+                let mut fingerprints = vec![F::default(); size];
+                for &(h, p) in stack.iter().rev() {
+                    let [a, b, c] = layout.slots(h);
+                    fingerprints[p] =
+                        F::from_hash(h) ^ fingerprints[a] ^ fingerprints[b] ^ fingerprints[c];
+                }
+
+                return Ok(Table {
+                    seed,
+                    len: hashes.len(),
+                    layout,
+                    fingerprints,
+                });
+            }
+        }
+        Err(Error::InvalidParameter("unable to construct filter"))
+    }
+
+    #[inline]
+    fn contains(&self, h: u64) -> bool {
+        if self.len == 0 {
+            return false;
+        }
+
+        let h = mix(h, self.seed);
+        let [a, b, c] = self.layout.slots(h);
+        let fps = &self.fingerprints;
+        F::from_hash(h) == fps[a] ^ fps[b] ^ fps[c]
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.fingerprints.capacity() * mem::size_of::<F>()
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        codec::put_u64(buf, self.seed);
+        codec::put_varint(buf, self.len as u64);
+        match self.layout {
+            Layout::Xor { block_length } => codec::put_varint(buf, block_length as u64),
+            Layout::BinaryFuse {
+                segment_length,
+                segment_count_length,
+            } => {
+                codec::put_varint(buf, segment_length as u64);
+                codec::put_varint(buf, segment_count_length as u64);
+            }
+        }
+        for &fp in self.fingerprints.iter() {
+            fp.put(buf);
+        }
+    }
+
+    fn decode(input: &mut &[u8], fuse: bool) -> Result<Table<F>, Error> {
+        let seed = codec::get_u64(input)?;
+        let len = codec::get_varint(input)? as usize;
+        let layout = if fuse {
+            let segment_length = codec::get_varint(input)? as usize;
+            let segment_count_length = codec::get_varint(input)? as usize;
+            Layout::BinaryFuse {
+                segment_length,
+                segment_count_length,
+            }
+        } else {
+            let block_length = codec::get_varint(input)? as usize;
+            Layout::Xor { block_length }
+        };
+        layout.check(fuse)?;
+
+        let bytes = codec::get_bytes(input, layout.size() * F::BYTES)?;
+        let fingerprints = bytes.chunks(F::BYTES).map(F::get).collect();
+        let table = Table {
+            seed,
+            len,
+            layout,
+            fingerprints,
+        };
+        table.check()?;
+        Ok(table)
+    }
+
+    /// Checks that a decoded or deserialized table, whose layout has already been checked, has a
+    /// fingerprint for each slot and no more keys than slots.
+    fn check(&self) -> Result<(), Error> {
+        let size = self.layout.size();
+        if self.len > size {
+            return Err(Error::InvalidData("too many keys"));
+        }
+
+        if self.fingerprints.len() != size {
+            return Err(Error::InvalidData("inconsistent number of fingerprints"));
+        }
+        Ok(())
+    }
+}
+
+impl Layout {
+    /// Returns the layout of a filter of `n` keys. Binary fuse filters are sized as in the authors'
+    /// reference implementation, which uses larger segments and relatively more slots for smaller
+    /// sets so that construction rarely fails.
+    fn new(n: usize, fuse: bool) -> Layout {
+        if !fuse {
+            let slots = 32 + (1.23 * n as f64).ceil() as usize;
+            return Layout::Xor {
+                block_length: slots.div_ceil(3),
+            };
+        }
+
+        let segment_length = if n == 0 {
+            4
+        } else {
+            let exp = ((n as f64).ln() / 3.33f64.ln() + 2.25).floor() as u32;
+            (1usize << exp).min(MAX_SEGMENT_LENGTH)
+        };
+        let slots = if n <= 1 {
+            0
+        } else {
+            let factor = (0.875 + 0.25 * 1e6f64.ln() / (n as f64).ln()).max(1.125);
+            (n as f64 * factor).round() as usize
+        };
+        let segments = slots.div_ceil(segment_length).saturating_sub(2).max(1);
+        Layout::BinaryFuse {
+            segment_length,
+            segment_count_length: segments * segment_length,
+        }
+    }
+
+    /// Checks that a decoded or deserialized layout is of the expected kind and its slots can be
+    /// indexed.
+    fn check(&self, fuse: bool) -> Result<(), Error> {
+        match *self {
+            Layout::Xor { block_length } if !fuse => {
+                if block_length == 0 || block_length > u32::MAX as usize {
+                    return Err(Error::InvalidData("invalid block length"));
+                }
+            }
+            Layout::BinaryFuse {
+                segment_length,
+                segment_count_length,
+            } if fuse => {
+                if !segment_length.is_power_of_two()
+                    || segment_length > MAX_SEGMENT_LENGTH
+                    || segment_count_length == 0
+                    || !segment_count_length.is_multiple_of(segment_length)
+                    || segment_count_length > u32::MAX as usize
+                {
+                    return Err(Error::InvalidData("invalid segments"));
+                }
+            }
+            _ => return Err(Error::InvalidData("unexpected layout")),
+        }
+        Ok(())
+    }
+
+    /// Returns the number of slots.
+    fn size(&self) -> usize {
+        match *self {
+            Layout::Xor { block_length } => 3 * block_length,
+            Layout::BinaryFuse {
+                segment_length,
+                segment_count_length,
+            } => segment_count_length + 2 * segment_length,
+        }
+    }
+
+    /// Returns the three slots of a mixed hash.
+    #[inline]
+    fn slots(&self, h: u64) -> [usize; 3] {
+        match *self {
+            Layout::Xor { block_length } => {
+                let reduce = |x: u64| ((x as u32 as u64 * block_length as u64) >> 32) as usize;
+                [
+                    reduce(h),
+                    block_length + reduce(h.rotate_left(21)),
+                    2 * block_length + reduce(h.rotate_left(42)),
+                ]
+            }
+            Layout::BinaryFuse {
+                segment_length,
+                segment_count_length,
+            } => {
+                let mask = (segment_length - 1) as u64;
+                let h0 = ((u128::from(h) * segment_count_length as u128) >> 64) as u64;
+                let h1 = (h0 + segment_length as u64) ^ ((h >> 18) & mask);
+                let h2 = (h0 + 2 * segment_length as u64) ^ (h & mask);
+                [h0 as usize, h1 as usize, h2 as usize]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bloomfilter::BloomFilter;
+
+    fn keys(n: u64) -> Vec<u64> {
+        (0..n).collect()
+    }
+
+    #[test]
+    fn contains() {
+        for &n in &[0, 1, 2, 10, 1000, 100_000] {
+            let keys = keys(n);
+            let xor = XorFilter8::new(&keys);
+            let fuse8 = BinaryFuseFilter8::new(&keys);
+            let fuse16 = BinaryFuseFilter16::new(&keys);
+            assert_eq!(xor.len(), n as usize);
+            assert_eq!(fuse8.len(), n as usize);
+            assert!(keys.iter().all(|k| xor.contains(k)));
+            assert!(keys.iter().all(|k| fuse8.contains(k)));
+            assert!(keys.iter().all(|k| fuse16.contains(k)));
+        }
+
+        assert!(!XorFilter8::<u64>::new(&[]).contains(&1));
+        assert!(BinaryFuseFilter8::<u64>::new(&[]).is_empty());
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        let err = Some(Error::InvalidParameter("duplicate keys"));
+        assert_eq!(XorFilter8::try_new(&[1, 2, 1]).err(), err);
+        assert_eq!(BinaryFuseFilter8::try_new(&[1, 2, 1]).err(), err);
+        assert_eq!(BinaryFuseFilter16::try_new(&[1, 2, 1]).err(), err);
+    }
+
+    #[test]
+    fn false_positive_rate() {
+        let n = 100_000;
+        let keys = keys(n);
+        let xor = XorFilter8::new(&keys);
+        let fuse8 = BinaryFuseFilter8::new(&keys);
+        let fuse16 = BinaryFuseFilter16::new(&keys);
+
+        let trials = 300_000;
+        let rate = |f: &dyn Fn(&u64) -> bool| {
+            (n..n + trials).filter(|k| f(k)).count() as f64 / trials as f64
+        };
+        assert!((rate(&|k| xor.contains(k)) - 1.0 / 256.0).abs() < 0.0005);
+        assert!((rate(&|k| fuse8.contains(k)) - 1.0 / 256.0).abs() < 0.0005);
+        assert!(rate(&|k| fuse16.contains(k)) < 0.0001);
+    }
+
+    #[test]
+    fn size() {
+        let n = 1_000_000;
+        let keys = keys(n);
+        let xor = XorFilter8::new(&keys);
+        let fuse8 = BinaryFuseFilter8::new(&keys);
+        let fuse16 = BinaryFuseFilter16::new(&keys);
+
+        let bits_per_key = |bytes: usize| (bytes * 8) as f64 / n as f64;
+        assert!(bits_per_key(xor.memory_usage()) < 1.24 * 8.0);
+        assert!(bits_per_key(fuse8.memory_usage()) < 1.15 * 8.0);
+        assert!(bits_per_key(fuse16.memory_usage()) < 1.15 * 16.0);
+
+        // All are smaller than a Bloom filter with a comparable false positive probability.
+        let bf = BloomFilter::<u64>::new(n as usize, 1.0 / 256.0);
+        assert!(xor.memory_usage() < bf.memory_usage());
+        assert!(fuse8.memory_usage() < xor.memory_usage());
+    }
+
+    #[test]
+    fn custom_hasher() {
+        let keys = keys(1000);
+        let fuse = BinaryFuseFilter8::with_hasher(&keys, SipBuildHasher::new(1, 2));
+        assert_eq!(fuse.hasher(), &SipBuildHasher::new(1, 2));
+        assert!(keys.iter().all(|k| fuse.contains(k)));
+    }
+
+    #[test]
+    fn to_and_from_bytes() {
+        let keys = keys(1000);
+
+        let xor = XorFilter8::new(&keys);
+        let decoded = XorFilter8::<u64>::from_bytes(&xor.to_bytes()).unwrap();
+        assert_eq!(decoded.table, xor.table);
+
+        let fuse8 = BinaryFuseFilter8::new(&keys);
+        let decoded = BinaryFuseFilter8::<u64>::from_bytes(&fuse8.to_bytes()).unwrap();
+        assert_eq!(decoded.table, fuse8.table);
+
+        let fuse16 = BinaryFuseFilter16::with_hasher(&keys, SipBuildHasher::new(1, 2));
+        let decoded = BinaryFuseFilter16::<u64>::from_bytes(&fuse16.to_bytes()).unwrap();
+        assert_eq!(decoded.table, fuse16.table);
+        assert!(keys.iter().all(|k| decoded.contains(k)));
+
+        assert_eq!(
+            BinaryFuseFilter8::<u64>::from_bytes(&xor.to_bytes()).err(),
+            Some(Error::UnexpectedType(codec::XOR_FILTER8))
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let fuse = BinaryFuseFilter16::new(&[1, 2, 3]);
+
+        let json = ::serde_json::to_string(&fuse).unwrap();
+        let decoded: BinaryFuseFilter16<i32> = ::serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.table, fuse.table);
+        assert!(decoded.contains(&2));
+
+        let segment_length = match fuse.table.layout {
+            Layout::BinaryFuse { segment_length, .. } => segment_length,
+            Layout::Xor { .. } => unreachable!(),
+        };
+        let segment_length = format!("\"segment_length\":{}", segment_length);
+        for &(from, to) in &[
+            (segment_length.as_str(), "\"segment_length\":0"),
+            (segment_length.as_str(), "\"segment_length\":3"),
+            ("\"fingerprints\":[", "\"fingerprints\":[1,"),
+            ("\"len\":3", "\"len\":1000"),
+        ] {
+            let corrupt = json.replacen(from, to, 1);
+            assert_ne!(corrupt, json);
+            assert!(::serde_json::from_str::<BinaryFuseFilter16<i32>>(&corrupt).is_err());
+        }
+
+        // A binary fuse filter's table isn't a valid xor filter.
+        assert!(::serde_json::from_str::<XorFilter8<i32>>(&json).is_err());
+        let xor = ::serde_json::to_string(&XorFilter8::new(&[1, 2, 3])).unwrap();
+        let truncated = xor.replacen("\"fingerprints\":[0,", "\"fingerprints\":[", 1);
+        assert_ne!(truncated, xor);
+        assert!(::serde_json::from_str::<XorFilter8<i32>>(&xor).is_ok());
+        assert!(::serde_json::from_str::<XorFilter8<i32>>(&truncated).is_err());
+    }
+}